@@ -1,19 +1,86 @@
-use std::{io::{Cursor, SeekFrom}, path::{Path, PathBuf}, collections::HashMap};
+use std::{collections::HashMap, io::{SeekFrom, Write}, path::{Path, PathBuf}};
 
-use super::{Reference, header::{*, self}, nodes::*, make_reference};
-use super::nodes::file::FileAttr;
-use super::table::Table;
+use super::{Reference, header::{*, self}, nodes::*, make_reference, iter};
+use super::nodes::file::{FileAttr, Source};
+use super::table::{NameEncoding, Table};
+use super::error::{RarcError, RarcResult};
+use super::manifest::Manifest;
+use super::preserve::PreservedLayout;
+use super::compression::Level;
+use super::rules::{self, AttrRule};
 use binrw::prelude::*;
 
+#[derive(Debug, Default, Clone, Copy)]
+/// Extra work [Archive::unpack_with] can do on top of unpacking.
+pub struct UnpackOptions {
+    /// Write a [Manifest] next to the unpacked root dir.
+    pub manifest: bool,
+    /// Decompress files flagged [FileAttr::COMPRESSED].
+    pub decompress: bool
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// The order [Archive::import_with] adds each folder's entries in, which
+/// decides node order and ids. Never left to the filesystem, so the same dir
+/// packs the same everywhere.
+pub enum ImportOrder {
+    /// By name, byte for byte.
+    #[default]
+    Alphabetical,
+    /// By name ignoring ASCII case, ties broken byte for byte.
+    CaseInsensitive,
+    /// Files before folders, each by name.
+    FilesFirst,
+    /// Paths below the imported dir, `/` separated, in the order given.
+    /// Entries not listed follow by name.
+    Explicit(Vec<String>)
+}
+
+#[derive(Debug, Clone)]
+/// How [Archive::import_with] brings files in.
+pub struct ImportOptions {
+    /// Given to files no rule matches.
+    pub attr: FileAttr,
+    /// Checked in order, the first one matching a file gives its attributes.
+    pub rules: Vec<AttrRule>,
+    pub order: ImportOrder,
+    /// Name encoding to pack with, over whatever a [Manifest] recorded. Set
+    /// before [ImportOptions::check_collisions] hashes any name.
+    pub encoding: Option<NameEncoding>,
+    /// Fail on [Archive::folder_collisions]. Only RARC looks names up by
+    /// hash, turn this off when writing U8 or SARC.
+    pub check_collisions: bool
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            attr: FileAttr::FILE | FileAttr::LOAD_TO_MRAM,
+            rules: vec![],
+            order: ImportOrder::default(),
+            encoding: None,
+            check_collisions: true
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 /// A JKRArchive. Contains everything needed to unpack, repack and modify the 
 /// files and folders inside.
 pub struct Archive {
     pub header: Header,
     pub data_header: DataHeader,
+    /// Owns every Directory, nodes only point at them weakly. Dropping one
+    /// from here drops it for good.
     pub folders: Vec<Reference<Directory>>,
     pub files: Vec<Reference<File>>,
-    pub root: Reference<Directory>
+    pub root: Reference<Directory>,
+    /// Filled by [Archive::read], lets [Archive::write] reproduce the input.
+    pub preserved: Option<PreservedLayout>,
+    /// Used for files flagged [FileAttr::COMPRESSED] that aren't yet.
+    pub compression: Level,
+    /// How names are stored in the string table, set before [Archive::read].
+    pub encoding: NameEncoding
 }
 
 impl Archive {
@@ -30,18 +97,23 @@ impl Archive {
         &mut self.data_header.next_idx
     }
 
-    pub fn read<R: BinReaderExt>(&mut self, reader: &mut R) -> BinResult<()> {
+    pub fn read<R: BinReaderExt>(&mut self, reader: &mut R) -> RarcResult<()> {
         let endian;
         (endian, self.header, self.data_header) = header::read_headers(reader)?;
+        let stream_len = reader.seek(SeekFrom::End(0))?;
+        header::check_node_counts(&self.header, &self.data_header, stream_len)?;
         self.folders.reserve_exact(self.data_header.dir_node_count as _);
         self.files.reserve_exact(self.data_header.file_node_count as _);
         let table = self.read_table(reader)?;
         let mut off = self.data_header.dir_node_off + self.header.data_header_off;
         reader.seek(SeekFrom::Start(off as _))?;
-        for i in 0..self.data_header.dir_node_count {
+        for i in 0..self.data_header.dir_node_count as usize {
             let mut node = Directory::default();
-            node.read(reader, endian)?;
-            node.name = table[&node.node.name_off].clone();
+            let pos = reader.stream_position()?;
+            node.read(reader, endian)
+                .map_err(RarcError::section("directory node", pos))?;
+            node.name = table.get(&node.node.name_off).cloned()
+                .ok_or(RarcError::BadNameOffset { node: i, offset: node.node.name_off })?;
             if i == 0 {
                 node.is_root = true;
                 self.root = make_reference(node);
@@ -52,50 +124,105 @@ impl Archive {
         }
         off = self.data_header.file_node_off + self.header.data_header_off;
         reader.seek(SeekFrom::Start(off as _))?;
-        for _ in 0..self.data_header.file_node_count {
+        for i in 0..self.data_header.file_node_count as usize {
             let mut node = File::default();
-            node.read(reader, endian)?;
-            node.name = table[&(node.name_off as u32)].clone();
+            let pos = reader.stream_position()?;
+            node.read(reader, endian)
+                .map_err(RarcError::section("file node", pos))?;
+            node.name = table.get(&node.name_off).cloned()
+                .ok_or(RarcError::BadNameOffset { node: i, offset: node.name_off })?;
             reader.seek(SeekFrom::Current(4))?;
             let node = make_reference(node);
             {
                 let mut nlock = node.borrow_mut();
                 if nlock.is_dir() && nlock.node.data != u32::MAX {
-                    let index = nlock.node.data as usize;
-                    let folder = &self.folders[index];
-                    nlock.folder = Some(folder.clone());
+                    let index = nlock.node.data;
+                    let folder = self.folders.get(index as usize)
+                        .ok_or(RarcError::BadDirectoryIndex { node: i, index })?;
+                    nlock.folder = Some(folder.downgrade());
                     let mut lock = folder.borrow_mut();
                     if lock.node.hash == nlock.node.hash {
-                        lock.file = Some(node.clone());
+                        lock.file = Some(node.downgrade());
                     }
                 } else if nlock.is_file() {
-                    let pos = self.header.file_data_off + self.header.data_header_off + nlock.node.data;
-                    let len = nlock.node.data_size as _;
-                    nlock.data.resize(len, 0);
+                    let pos = (self.header.file_data_off + self.header.data_header_off) as u64
+                        + nlock.node.data as u64;
+                    let len = nlock.node.data_size as u64;
+                    if pos + len > stream_len {
+                        return Err(RarcError::Truncated { section: "file data", offset: pos });
+                    }
+                    nlock.data.resize(len as _, 0);
                     let current = reader.stream_position()?;
-                    reader.seek(SeekFrom::Start(pos as _))?;
+                    reader.seek(SeekFrom::Start(pos))?;
                     reader.read_exact(&mut nlock.data)?;
                     reader.seek(SeekFrom::Start(current))?;
                 }
             }
             self.files.push(node);
         }
-        for node in &self.folders {
+        for (i, node) in self.folders.iter().enumerate() {
             let mut lock = node.borrow_mut();
             let off = lock.node.file_off as usize;
-                let count = lock.node.file_count as usize;
-                for i in off..(off + count) {
-                    let file = &self.files[i];
-                    let mut fileref = file.borrow_mut();
-                    fileref.parent = Some(node.clone());
-                    lock.children.push(file.clone());
-                }
+            let count = lock.node.file_count as usize;
+            for index in off..(off + count) {
+                let file = self.files.get(index)
+                    .ok_or(RarcError::BadFileIndex { node: i, index: index as u32 })?;
+                let mut fileref = file.borrow_mut();
+                fileref.parent = Some(node.downgrade());
+                lock.children.push(file.clone());
+            }
         }
+        self.preserved = Some(PreservedLayout { table });
         Ok(())
     }
-    pub fn unpack<A: AsRef<Path>>(&self, dir: A) -> std::io::Result<PathBuf> {
-        self.root.borrow().unpack(dir.as_ref())?;
-        Ok(dir.as_ref().join(&self.root.borrow().name))
+    pub fn unpack<A: AsRef<Path>>(&self, dir: A) -> RarcResult<PathBuf> {
+        self.unpack_with(dir, UnpackOptions::default())
+    }
+    /// Ditto of [Archive::unpack], with [UnpackOptions].
+    pub fn unpack_with<A: AsRef<Path>>(&self, dir: A, options: UnpackOptions) -> RarcResult<PathBuf> {
+        let root = self.root.borrow();
+        root.unpack_with(dir.as_ref(), options.decompress)?;
+        let path = dir.as_ref().join(&root.name);
+        if options.manifest {
+            self.manifest().write(Manifest::sidecar(&path))?;
+        }
+        Ok(path)
+    }
+    /// Unpacks only the nodes whose full path matches one of `patterns` into
+    /// `dir`, keeping their in-archive paths. Matched folders are unpacked
+    /// with all their children. Returns every path that was written.
+    pub fn extract<A: AsRef<Path>, S: AsRef<str>>(&self, patterns: &[S], dir: A)
+        -> RarcResult<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let mut result = vec![];
+        let mut done: Vec<String> = vec![];
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            let root_name = self.root.borrow().name.clone();
+            if iter::compile_glob(pattern)?.matches(&root_name) {
+                result.push(self.unpack(dir)?);
+                return Ok(result);
+            }
+            for file in self.find_by_glob(pattern)? {
+                let file = file.borrow();
+                let path = iter::archive_path(&file);
+                if done.iter().any(|x| path == *x || path.starts_with(&format!("{}/", x))) {
+                    continue;
+                }
+                let target = dir.join(file.to_string());
+                if file.is_dir() && let Some(folder) = file.get_folder() {
+                    folder.borrow().unpack(&target)?;
+                } else if file.is_file() {
+                    if let Some(parent) = target.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::io::copy(&mut file.open_data()?, &mut std::fs::File::create(&target)?)?;
+                }
+                done.push(path);
+                result.push(target);
+            }
+        }
+        Ok(result)
     }
 
     fn recalc_file_indicies(&mut self) {
@@ -121,7 +248,14 @@ impl Archive {
         }
     }
 
-    fn sort_nodes(&mut self, node: Reference<Directory>) {
+    fn folder_index(&self, folder: &Reference<Directory>) -> RarcResult<u32> {
+        self.folders.iter()
+            .position(|x| Reference::ptr_eq(x, folder))
+            .map(|x| x as u32)
+            .ok_or_else(|| RarcError::UnlinkedDirectory { name: folder.borrow().name.clone() })
+    }
+
+    fn sort_nodes(&mut self, node: Reference<Directory>) -> RarcResult<()> {
         let mut shortcuts = Vec::new();
         let mut folders = Vec::new();
         for file in node.borrow().children.clone() {
@@ -132,21 +266,17 @@ impl Archive {
             }
         }
         for shortcut in shortcuts {
-            let index = match shortcut.borrow().folder.as_ref() {
-                Some(folder) => {
-                    self.folders.iter()
-                    .position(|x| {
-                        x == folder
-                    })
-                    .map(|x| x as u32).unwrap()
-                },
+            let index = match shortcut.borrow().get_folder() {
+                Some(folder) => self.folder_index(&folder)?,
                 None => u32::MAX
             };
             shortcut.borrow_mut().node.data = index;
-            let shidx = node.borrow().children.iter()
-            .position(|x| x == &shortcut).unwrap();
-            node.borrow_mut().children.remove(shidx as usize);
-            node.borrow_mut().children.push(shortcut.clone());
+            let mut dir = node.borrow_mut();
+            if let Some(shidx) = dir.children.iter()
+                .position(|x| Reference::ptr_eq(x, &shortcut)) {
+                let shortcut = dir.children.remove(shidx);
+                dir.children.push(shortcut);
+            }
         }
         node.borrow_mut().node.file_off = self.files.len() as u32;
         let child_count = node.borrow().children.len() as u16;
@@ -155,34 +285,37 @@ impl Archive {
             self.files.push(child.clone());
         }
         for dir in folders {
-            if let Ok(mut dir_ref) = dir.try_borrow_mut() {
-                let folder = dir_ref.folder.as_ref().unwrap();
-                let index = self.folders.iter()
-                    .position(|x| {
-                        x == folder
-                    }).unwrap();
-                dir_ref.node.data = index as u32;
-            }
-            let folder = {
-                let dref = dir.borrow();
-                let folder = dref.folder.as_ref().unwrap();
-                folder.clone()
-            };
-            self.sort_nodes(folder);
+            let folder = dir.borrow().get_folder()
+                .ok_or_else(|| RarcError::UnlinkedDirectory { name: dir.borrow().name.clone() })?;
+            dir.borrow_mut().node.data = self.folder_index(&folder)?;
+            self.sort_nodes(folder)?;
         }
+        Ok(())
+    }
+
+    /// Rebuilds the file nodes and indices from the tree. Fails if two
+    /// siblings share a name hash, see [Archive::folder_collisions].
+    pub fn sort(&mut self) -> RarcResult<()> {
+        self.sort_unchecked()?;
+        self.check_collisions()
     }
 
-    pub fn sort(&mut self) {
+    /// Ditto of [Archive::sort] without the collision check, for formats
+    /// that don't look names up by hash.
+    pub(crate) fn sort_unchecked(&mut self) -> RarcResult<()> {
         self.files.clear();
-        self.sort_nodes(self.root.clone());
+        self.sort_nodes(self.root.clone())?;
         self.recalc_file_indicies();
+        Ok(())
     }
 
     pub fn create_folder<A: AsRef<str>>(&mut self, name: A, parent: Option<Reference<Directory>>)
         -> Reference<Directory> {
         let name = name.as_ref();
-        let mut dir = Directory::default();
-        dir.name = name.into();
+        let mut dir = Directory {
+            name: name.into(),
+            ..Default::default()
+        };
         dir.node.short_name = dir.short_name();
         let true_dir = make_reference(dir);
         let file = 
@@ -193,7 +326,7 @@ impl Archive {
             Some(true_dir.clone()), Some(true_dir.clone()));
         File::create("..", FileAttr::FOLDER,
         parent.clone(), Some(true_dir.clone()));
-        true_dir.borrow_mut().file = Some(file);
+        true_dir.borrow_mut().file = Some(file.downgrade());
         self.folders.push(true_dir.clone());
         true_dir
     }
@@ -230,19 +363,49 @@ impl Archive {
         arch
     }
 
+    /// Imports every file and folder inside `path`, giving files `attr`.
+    /// If a [Manifest] sidecar sits next to `path` it's honored.
     pub fn import<A: AsRef<Path>>(&mut self, path: A, attr: FileAttr) 
-        -> std::io::Result<()> {
-        self.import_node(path, attr, Some(self.root.clone()))?;
-        self.sort();
-        Ok(())
+        -> RarcResult<()> {
+        self.import_with(path, &ImportOptions { attr, ..Default::default() })
     }
 
-    fn import_node<A: AsRef<Path>>(&mut self, path: A, attr: FileAttr, parent: Option<Reference<Directory>>) 
-        -> std::io::Result<()> {
+    /// Ditto of [Archive::import], with [ImportOptions]. A [Manifest]
+    /// sidecar still has the last word on attributes of nodes it knows.
+    pub fn import_with<A: AsRef<Path>>(&mut self, path: A, options: &ImportOptions)
+        -> RarcResult<()> {
+        let path = path.as_ref();
+        let explicit = match &options.order {
+            ImportOrder::Explicit(paths) => paths.iter().enumerate().map(|(i, x)| (x.as_str(), i)).collect(),
+            _ => HashMap::new()
+        };
+        self.import_node(path, options, &explicit, "", Some(self.root.clone()))?;
+        let sidecar = Manifest::sidecar(path);
+        if sidecar.is_file() {
+            let manifest = Manifest::read(sidecar)?;
+            self.apply_manifest(&manifest)?;
+        } else {
+            self.sort_unchecked()?;
+        }
+        if let Some(encoding) = options.encoding {
+            self.encoding = encoding;
+        }
+        match options.check_collisions {
+            true => self.check_collisions(),
+            false => Ok(())
+        }
+    }
+
+    /// `prefix` is the `/` separated path of `path` below the imported dir,
+    /// matched against [ImportOptions::rules] and `explicit`, the index of
+    /// every path in [ImportOrder::Explicit].
+    fn import_node<A: AsRef<Path>>(&mut self, path: A, options: &ImportOptions, explicit: &HashMap<&str, usize>,
+        prefix: &str, parent: Option<Reference<Directory>>) -> std::io::Result<()> {
         let path = path.as_ref();
         if !path.is_dir() {
             return Ok(());
         }
+        let mut entries = vec![];
         for entry in path.read_dir()? {
             let entry = entry?;
             let name = entry.file_name()
@@ -250,22 +413,35 @@ impl Archive {
             if name == "." || name == ".." {
                 continue;
             }
-            if entry.path().is_dir() {
+            let path = entry.path();
+            entries.push((format!("{}{}", prefix, name), name, path.is_dir(), path));
+        }
+        match &options.order {
+            ImportOrder::Alphabetical => entries.sort_by(|x, y| x.1.cmp(&y.1)),
+            ImportOrder::CaseInsensitive => entries.sort_by(|x, y|
+                (x.1.to_ascii_lowercase(), &x.1).cmp(&(y.1.to_ascii_lowercase(), &y.1))),
+            ImportOrder::FilesFirst => entries.sort_by(|x, y| (x.2, &x.1).cmp(&(y.2, &y.1))),
+            ImportOrder::Explicit(_) => entries.sort_by(|x, y| {
+                let (a, b) = (explicit.get(x.0.as_str()), explicit.get(y.0.as_str()));
+                (a.is_none(), a, &x.1).cmp(&(b.is_none(), b, &y.1))
+            })
+        }
+        for (relative, name, is_dir, path) in entries {
+            if is_dir {
                 let node = 
                 self.create_folder(name, parent.clone());
-                self.import_node(entry.path(), attr, Some(node))?;
-            } else if entry.path().is_file() {
+                self.import_node(path, options, explicit, &format!("{}/", relative), Some(node))?;
+            } else if path.is_file() {
+                let attr = rules::attr_for(&options.rules, &relative, options.attr);
                 let node = self.create_file(name, attr, parent.clone());
-                node.borrow_mut().data = std::fs::read(entry.path())?;
-                let size = node.borrow().data.len() as u32;
-                node.borrow_mut().node.data_size = size;
+                node.borrow_mut().set_source(Source::Path(path))?;
             }
         }
         Ok(())
     }
 
     pub fn gen_table(&self) -> Table {
-        let mut table = Table::default();
+        let mut table = Table::new(self.encoding);
         table.add(".");
         table.add("..");
         table.add(&self.root.borrow().name);
@@ -274,78 +450,51 @@ impl Archive {
         table
     }
 
-    pub fn write<W: BinWriterExt>(&self, writer: &mut W, endian: binrw::Endian) -> BinResult<()> {
-        let mut mram = vec![];
-        let mut aram = vec![];
-        let mut dvd = vec![];
-        for file in &self.files {
-            let fref = file.borrow();
-            if fref.attr.contains(FileAttr::LOAD_TO_MRAM) {
-                mram.push(file.clone());
-            } else if fref.attr.contains(FileAttr::LOAD_TO_ARAM) {
-                aram.push(file.clone());
-            } else if fref.attr.contains(FileAttr::LOAD_FROM_DVD) {
-                dvd.push(file.clone());
+    /// Writes this Archive. An Archive that was read and still matches its
+    /// [PreservedLayout] is written back exactly as it was, anything else
+    /// gets a freshly generated layout. Only ever writes forward, see
+    /// [Archive::layout].
+    pub fn write<W: Write>(&self, writer: &mut W, endian: binrw::Endian) -> RarcResult<()> {
+        let layout = self.layout()?;
+        self.write_layout(writer, endian, &layout)
+    }
+
+    /// Checks that every name fits [Archive::encoding].
+    pub(crate) fn check_names(&self) -> RarcResult<()> {
+        let folders = self.folders.iter().map(|x| x.borrow().name.clone());
+        let files = self.files.iter().map(|x| x.borrow().name.clone());
+        for name in folders.chain(files) {
+            if self.encoding.encode(&name).is_none() {
+                return Err(RarcError::UnencodableName { name, encoding: self.encoding });
             }
         }
-        let dnodecount = self.folders.len() as u32;
-        let fnodecount = self.files.len() as u32;
-        let dnodeoff = 0x40 + 
-            u32::next_multiple_of(dnodecount * 0x10, 32);
-        let stroff = dnodeoff +
-            u32::next_multiple_of(fnodecount * 0x14, 32);
-        let strdata = self.gen_table();
-        writer.seek(SeekFrom::Start(stroff as u64))?;
-        strdata.write(writer)?;
-        writer.seek(SeekFrom::Start(0x40))?;
-        for folder in &self.folders {
-            folder.borrow().write(writer,endian)?;
-        }
-        let end = writer.seek(SeekFrom::End(0))?.next_multiple_of(32);
-        while writer.stream_position()? < end {
-            0u8.write_ne(writer)?;
-        }
-        let fdataoff = writer.stream_position()? as u32 - 0x20;
-        let mram_size = write_file_data(writer, mram)?;
-        let aram_size = write_file_data(writer, aram)?;
-        let dvd_size = write_file_data(writer, dvd)?;
-        let total_size = mram_size + aram_size + dvd_size;
-        writer.seek(SeekFrom::Start(dnodeoff as u64))?;
-        for file in &self.files {
-            file.borrow().write(writer, endian)?;
-            0i32.write_ne(writer)?;
-        }
-        let whole_size = writer.seek(SeekFrom::End(0))? as u32;
-        writer.seek(SeekFrom::Start(0))?;
-        Magic::from_endian(endian).write_ne(writer)?;
-        let header = Header {
-            size: whole_size,
-            data_header_off: 0x20,
-            file_data_off: fdataoff,
-            file_data_len: total_size,
-            mram_size,
-            aram_size,
-            dvd_size
-        };
-        header.write_options(writer, endian, ())?;
-        let dataheader = DataHeader {
-            dir_node_count: dnodecount,
-            dir_node_off: 0x20,
-            file_node_count: fnodecount,
-            file_node_off: dnodeoff - 0x20,
-            string_tbl_size: strdata.total_size(),
-            string_tbl_off: stroff - 0x20,
-            next_idx: self.next_id(),
-            sync: self.sync()
-        };
-        dataheader.write_options(writer, endian, ())?;
         Ok(())
     }
 
-    pub fn to_bytes(&self, endian: binrw::Endian) -> BinResult<Vec<u8>> {
-        let mut writer = Cursor::new(vec![]);
-        self.write(&mut writer, endian)?;
-        Ok(writer.into_inner())
+    pub fn to_bytes(&self, endian: binrw::Endian) -> RarcResult<Vec<u8>> {
+        let layout = self.layout()?;
+        let mut result = Vec::with_capacity(layout.size as usize);
+        self.write_layout(&mut result, endian, &layout)?;
+        Ok(result)
+    }
+
+    /// Reads the Archive in `reader` and writes it to `writer` in the other
+    /// endian, RARC to CRAR or back, returning the endian written. Nodes,
+    /// names and layout are kept exactly, file data is copied as is since
+    /// its format is up to the game.
+    pub fn convert_endian<R: BinReaderExt, W: Write>(reader: &mut R, writer: &mut W)
+        -> RarcResult<binrw::Endian> {
+        let start = reader.stream_position()?;
+        let (endian, _, _) = header::read_headers(reader)?;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut archive = Archive { encoding: NameEncoding::Raw, ..Default::default() };
+        archive.read(reader)?;
+        let endian = match endian {
+            binrw::Endian::Big => binrw::Endian::Little,
+            binrw::Endian::Little => binrw::Endian::Big
+        };
+        archive.write(writer, endian)?;
+        Ok(endian)
     }
 }
 
@@ -358,36 +507,152 @@ fn collect_strings(table: &mut Table, node: Reference<Directory>) {
         } else if child.name == ".." {
             child.name_off = 2;
         } else {
-            child.name_off = table.add(&child.name) as u16;
+            child.name_off = table.add(&child.name);
         }
-        if child.is_dir() && !child.is_shortcut() {
-            if let Some(folder) = &child.folder {
-                {
-                    let mut folder = folder.borrow_mut();
-                    folder.node.name_off = child.name_off as u32;
-                }
-                collect_strings(table, folder.clone());
+        if child.is_dir() && !child.is_shortcut()
+            && let Some(folder) = child.get_folder() {
+            {
+                let mut folder = folder.borrow_mut();
+                folder.node.name_off = child.name_off;
             }
+            collect_strings(table, folder);
         }
     }
 }
 
-fn write_file_data<W: BinWriterExt>(writer: &mut W, files: Vec<Reference<File>>) -> BinResult<u32> {
-    let start = writer.stream_position()?;
-    let mut dict = HashMap::new();
-    for i in 0..files.len() {
-        let mut file = files[i].borrow_mut();
-        if let Some(offset) = dict.get(&file.data) {
-            file.node.data = *offset;
-            continue;
+/// [Archive] has to stay shareable across threads.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Archive>();
+};
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::Endian;
+
+    use super::*;
+    use crate::fixture::{put_u32, read, sample_bytes, temp_dir};
+
+    fn try_read(bytes: &[u8]) -> RarcResult<Archive> {
+        let mut archive = Archive::default();
+        archive.read(&mut Cursor::new(bytes))?;
+        Ok(archive)
+    }
+
+    /// Offsets of the directory and file nodes in `bytes`.
+    fn nodes(bytes: &[u8]) -> (usize, usize) {
+        let base = u32::from_be_bytes(bytes[8..12].try_into().unwrap()) as usize;
+        let dir = base + u32::from_be_bytes(bytes[base + 4..base + 8].try_into().unwrap()) as usize;
+        let file = base + u32::from_be_bytes(bytes[base + 12..base + 16].try_into().unwrap()) as usize;
+        (dir, file)
+    }
+
+    #[test]
+    fn read_back_what_was_written() {
+        let archive = read(&sample_bytes());
+        assert_eq!(archive.folders.len(), 3);
+        assert_eq!(archive.root.borrow().name, "stage");
+        let names: Vec<_> = archive.files.iter().map(|x| x.borrow().name.clone()).collect();
+        assert!(names.contains(&"c.bin".to_string()));
+        for (path, data) in [("stage/a.bin", b"first file".as_slice()), ("stage/jmp/b.bcsv", &[0xAB; 0x30]),
+            ("stage/jmp/Model/c.bin", b"nested")] {
+            assert_eq!(archive.get_file(path).unwrap().borrow().data, data, "{}", path);
+        }
+        assert_eq!(archive.to_bytes(Endian::Big).unwrap(), sample_bytes());
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let bytes = sample_bytes();
+        assert!(matches!(try_read(&[]), Err(RarcError::Truncated { section: "header", .. })));
+        let data = u32::from_be_bytes(bytes[12..16].try_into().unwrap()) as usize + 0x20;
+        for len in (0..data).step_by(7) {
+            assert!(matches!(try_read(&bytes[..len]), Err(RarcError::Truncated { .. })), "cut at {:#x}", len);
+        }
+        assert!(matches!(try_read(&bytes[..data + 4]), Err(RarcError::Truncated { section: "file data", .. })));
+    }
+
+    #[test]
+    fn forged_node_counts_are_errors() {
+        for (off, section) in [(0x20, "directory nodes"), (0x28, "file nodes")] {
+            let mut bytes = sample_bytes();
+            put_u32(&mut bytes, off, 0x0FFF_FFFF);
+            assert!(matches!(try_read(&bytes), Err(RarcError::Truncated { section: s, .. }) if s == section));
+        }
+    }
+
+    #[test]
+    fn bad_magic_is_an_error() {
+        let mut bytes = sample_bytes();
+        bytes[..4].copy_from_slice(b"ABCD");
+        assert!(matches!(try_read(&bytes), Err(RarcError::BadMagic { offset: 0, magic }) if &magic == b"ABCD"));
+    }
+
+    #[test]
+    fn bad_indices_are_errors() {
+        let (dir, file) = nodes(&sample_bytes());
+        let mut bytes = sample_bytes();
+        put_u32(&mut bytes, dir + 4, 0xFFFF);
+        assert!(matches!(try_read(&bytes), Err(RarcError::BadNameOffset { node: 0, offset: 0xFFFF })));
+        let mut bytes = sample_bytes();
+        put_u32(&mut bytes, dir + 12, 0x100);
+        assert!(matches!(try_read(&bytes), Err(RarcError::BadFileIndex { node: 0, .. })));
+        // The root's "." node points back at directory 0, make it point nowhere.
+        let archive = read(&sample_bytes());
+        let dot = archive.files.iter().position(|x| x.borrow().name == ".").unwrap();
+        let mut bytes = sample_bytes();
+        put_u32(&mut bytes, file + dot * 0x14 + 8, 7);
+        assert!(matches!(try_read(&bytes), Err(RarcError::BadDirectoryIndex { index: 7, .. })));
+    }
+
+    #[test]
+    fn convert_endian_round_trips() {
+        let mut bytes = sample_bytes();
+        // Names don't have to be valid in any encoding.
+        let off = bytes.windows(6).position(|x| x == b"a.bin\0").unwrap();
+        bytes[off] = 0xE9;
+        let (mut crar, mut rarc) = (vec![], vec![]);
+        assert_eq!(Archive::convert_endian(&mut Cursor::new(&bytes), &mut crar).unwrap(), Endian::Little);
+        assert_eq!(&crar[..4], b"CRAR");
+        assert_eq!(crar.len(), bytes.len());
+        assert_eq!(Archive::convert_endian(&mut Cursor::new(&crar), &mut rarc).unwrap(), Endian::Big);
+        assert_eq!(rarc, bytes);
+    }
+
+    /// Names of the nodes in `dir`, shortcuts left out.
+    fn children(archive: &Archive, dir: &str) -> Vec<String> {
+        archive.get_dir(dir).unwrap().borrow().children.iter()
+            .map(|x| x.borrow().name.clone())
+            .filter(|x| x != "." && x != "..")
+            .collect()
+    }
+
+    #[test]
+    fn import_order() {
+        let dir = temp_dir("import_order");
+        std::fs::create_dir_all(dir.join("stage/Dir")).unwrap();
+        for name in ["b.bin", "C.bin", "a.bin", "Dir/z.bin", "Dir/y.bin"] {
+            std::fs::write(dir.join("stage").join(name), name).unwrap();
         }
-        let offset = (writer.stream_position()? - start) as u32;
-        dict.insert(file.data.clone(), offset);
-        file.node.data = offset;
-        writer.write_all(&file.data)?;
-        while writer.stream_position()? % 32 != 0 {
-            0u8.write_ne(writer)?;
+        let explicit = ImportOrder::Explicit(vec!["b.bin".into(), "Dir/y.bin".into(), "Dir".into(), "gone.bin".into()]);
+        for (order, root, sub) in [
+            (ImportOrder::Alphabetical, ["C.bin", "Dir", "a.bin", "b.bin"], ["y.bin", "z.bin"]),
+            (ImportOrder::CaseInsensitive, ["a.bin", "b.bin", "C.bin", "Dir"], ["y.bin", "z.bin"]),
+            (ImportOrder::FilesFirst, ["C.bin", "a.bin", "b.bin", "Dir"], ["y.bin", "z.bin"]),
+            // Names the list leaves out follow by name.
+            (explicit, ["b.bin", "Dir", "C.bin", "a.bin"], ["y.bin", "z.bin"])
+        ] {
+            let mut archive = Archive::create("stage", true);
+            archive.import_with(dir.join("stage"), &ImportOptions { order: order.clone(), ..Default::default() }).unwrap();
+            assert_eq!(children(&archive, "stage"), root, "{:?}", order);
+            assert_eq!(children(&archive, "stage/Dir"), sub, "{:?}", order);
         }
+        let order = ImportOrder::Explicit(vec!["Dir/z.bin".into()]);
+        let mut archive = Archive::create("stage", true);
+        archive.import_with(dir.join("stage"), &ImportOptions { order, ..Default::default() }).unwrap();
+        assert_eq!(children(&archive, "stage/Dir"), ["z.bin", "y.bin"]);
+        std::fs::remove_dir_all(dir).unwrap();
     }
-    Ok((writer.stream_position()? - start) as u32)
-}
\ No newline at end of file
+}
@@ -1,4 +1,5 @@
 use std::ffi::*;
+use std::io::Cursor;
 use super::*;
 
 #[unsafe(no_mangle)]
@@ -12,7 +13,7 @@ pub extern "C" fn archive_to_dir(buffer: *const u8, size: usize, dir: *const i8)
     let dir = unsafe {
         CStr::from_ptr(dir)
     }.to_string_lossy().into_owned();
-    let data = decompres_yaz0(buffer);
+    let data = decompress(buffer);
     let mut reader = Cursor::new(data);
     let mut archive = Archive::default();
     if let Ok(_) = archive.read(&mut reader) {
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+use crate::Archive;
+use crate::error::{RarcError, RarcResult};
+use crate::iter::archive_path;
+use crate::nodes::calc_hash_bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Differently named nodes sharing a [calc_hash_bytes] hash.
+pub struct Collision {
+    pub hash: u16,
+    /// In-archive paths of every node with that hash.
+    pub paths: Vec<String>
+}
+
+impl Archive {
+    /// The hash `name` is stored with, in [Archive::encoding].
+    pub fn name_hash(&self, name: &str) -> u16 {
+        match self.encoding.encode(name) {
+            Some(bytes) => calc_hash_bytes(&bytes),
+            None => calc_hash_bytes(name.as_bytes())
+        }
+    }
+
+    /// Siblings sharing a hash, "." and ".." included. JKRArchive compares
+    /// hashes before names, so these can resolve to the wrong node in game.
+    pub fn folder_collisions(&self) -> Vec<Collision> {
+        let mut result = vec![];
+        for folder in &self.folders {
+            let mut hashes = BTreeMap::<u16, Vec<String>>::new();
+            // archive_path locks `folder` again, so don't hold it meanwhile.
+            let children = folder.borrow().children.clone();
+            for child in &children {
+                let child = child.borrow();
+                let paths = hashes.entry(self.name_hash(&child.name)).or_default();
+                if !paths.iter().any(|x| x.rsplit('/').next() == Some(child.name.as_str())) {
+                    paths.push(archive_path(&child));
+                }
+            }
+            result.extend(hashes.into_iter()
+                .filter(|x| x.1.len() > 1)
+                .map(|(hash, paths)| Collision { hash, paths }));
+        }
+        result
+    }
+
+    /// Every hash shared by differently named nodes anywhere in the Archive,
+    /// by hash. Only those within one folder break lookups, see
+    /// [Archive::folder_collisions], the rest are worth knowing before they
+    /// end up side by side.
+    pub fn hash_collisions(&self) -> Vec<Collision> {
+        let mut hashes = BTreeMap::<u16, BTreeMap<String, Vec<String>>>::new();
+        let root = self.root.borrow().name.clone();
+        hashes.entry(self.name_hash(&root)).or_default()
+            .entry(root.clone()).or_default().push(root);
+        for file in &self.files {
+            let file = file.borrow();
+            if file.is_shortcut() {
+                continue;
+            }
+            hashes.entry(self.name_hash(&file.name)).or_default()
+                .entry(file.name.clone()).or_default().push(archive_path(&file));
+        }
+        hashes.into_iter()
+            .filter(|x| x.1.len() > 1)
+            .map(|(hash, names)| Collision { hash, paths: names.into_values().flatten().collect() })
+            .collect()
+    }
+
+    /// Fails on the first [Archive::folder_collisions].
+    pub(crate) fn check_collisions(&self) -> RarcResult<()> {
+        match self.folder_collisions().into_iter().next() {
+            Some(Collision { hash, mut paths }) => Err(RarcError::HashCollision {
+                other: paths.pop().unwrap_or_default(),
+                path: paths.swap_remove(0),
+                hash
+            }),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::Endian;
+
+    use crate::FileAttr;
+    use crate::archive::ImportOptions;
+    use crate::fixture::{sample, temp_dir};
+    use crate::table::NameEncoding;
+    use crate::verify::verify;
+    use super::*;
+
+    /// Names hashing the same: 'a' * 3 + 'b' == 'b' * 3 + '_'.
+    const NAMES: [&str; 2] = ["ab", "b_"];
+
+    #[test]
+    fn collisions_are_found() {
+        let mut archive = sample();
+        assert_eq!(archive.name_hash(NAMES[0]), archive.name_hash(NAMES[1]));
+        archive.add_file("stage", NAMES[0], vec![1], FileAttr::LOAD_TO_MRAM).unwrap();
+        archive.add_file("stage/jmp", NAMES[1], vec![2], FileAttr::LOAD_TO_MRAM).unwrap();
+        assert_eq!(archive.folder_collisions(), vec![]);
+        assert_eq!(archive.hash_collisions(), vec![Collision {
+            hash: archive.name_hash(NAMES[0]),
+            paths: vec!["stage/ab".into(), "stage/jmp/b_".into()]
+        }]);
+        // The same name in two folders isn't a collision.
+        archive.add_file("stage/jmp", "a.bin", vec![3], FileAttr::LOAD_TO_MRAM).unwrap();
+        assert_eq!(archive.hash_collisions().len(), 1);
+    }
+
+    fn collides<T>(result: RarcResult<T>) -> bool {
+        matches!(result, Err(RarcError::HashCollision { .. }))
+    }
+
+    #[test]
+    fn colliding_edits_fail() {
+        let mut archive = sample();
+        archive.add_file("stage", NAMES[0], vec![1], FileAttr::LOAD_TO_MRAM).unwrap();
+        archive.add_file("stage/jmp", NAMES[1], vec![2], FileAttr::LOAD_TO_MRAM).unwrap();
+        assert!(collides(archive.add_file("stage", NAMES[1], vec![], FileAttr::LOAD_TO_MRAM)));
+        assert!(collides(archive.add_dir("stage", NAMES[1])));
+        assert!(collides(archive.rename("stage/a.bin", NAMES[1])));
+        assert!(collides(archive.move_to("stage/jmp/b_", "stage")));
+        // Renaming a node to its own name or hash is fine.
+        archive.rename("stage/ab", NAMES[0]).unwrap();
+        assert!(archive.hash_collisions().len() == 1 && archive.folder_collisions().is_empty());
+    }
+
+    #[test]
+    fn colliding_archives_fail_to_sort() {
+        let mut archive = sample();
+        let root = archive.root.clone();
+        for name in NAMES {
+            archive.create_file(name, FileAttr::FILE | FileAttr::LOAD_TO_MRAM, Some(root.clone()));
+        }
+        assert_eq!(archive.folder_collisions().len(), 1);
+        assert!(matches!(archive.sort(), Err(RarcError::HashCollision { hash, .. }) if hash == archive.name_hash("ab")));
+    }
+
+    #[test]
+    fn colliding_archives_still_write() {
+        let mut archive = sample();
+        let root = archive.root.clone();
+        for name in NAMES {
+            archive.create_file(name, FileAttr::FILE | FileAttr::LOAD_TO_MRAM, Some(root.clone()));
+        }
+        archive.sort_unchecked().unwrap();
+        let bytes = archive.to_bytes(Endian::Big).unwrap();
+        let report = verify(&mut Cursor::new(bytes), NameEncoding::Utf8).unwrap();
+        assert!(report.errors().any(|x| x.message.contains("\"ab\" and \"b_\" in \"stage\" both hash to")));
+    }
+
+    #[test]
+    fn import_checks_collisions_only_when_asked() {
+        let dir = temp_dir("collision_import");
+        std::fs::create_dir_all(dir.join("stage")).unwrap();
+        for name in NAMES {
+            std::fs::write(dir.join("stage").join(name), name).unwrap();
+        }
+        let mut archive = Archive::create("stage", true);
+        assert!(collides(archive.import_with(dir.join("stage"), &ImportOptions::default())));
+        let mut archive = Archive::create("stage", true);
+        let options = ImportOptions { check_collisions: false, ..Default::default() };
+        archive.import_with(dir.join("stage"), &options).unwrap();
+        let mut u8 = vec![];
+        archive.write_u8(&mut u8).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn import_hashes_in_the_packing_encoding() {
+        // "ア" is 0x83 0x41 in Shift-JIS: 0x83 * 3 + 0x41 == 'x' * 3 + 'b'.
+        let dir = temp_dir("collision_encoding");
+        std::fs::create_dir_all(dir.join("stage")).unwrap();
+        for name in ["ア", "xb"] {
+            std::fs::write(dir.join("stage").join(name), name).unwrap();
+        }
+        let mut archive = Archive::create("stage", true);
+        archive.import_with(dir.join("stage"), &ImportOptions::default()).unwrap();
+        let mut archive = Archive::create("stage", true);
+        let options = ImportOptions { encoding: Some(NameEncoding::ShiftJis), ..Default::default() };
+        assert!(collides(archive.import_with(dir.join("stage"), &options)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
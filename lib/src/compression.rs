@@ -0,0 +1,150 @@
+use std::borrow::Cow;
+
+use yaz0::CompressionLevel;
+
+use crate::{FileAttr, szs, yay0};
+use crate::error::RarcResult;
+
+/// Magic at the start of every Yaz0 stream.
+pub const YAZ0_MAGIC: &[u8; 4] = szs::MAGIC;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How hard to look for matches when compressing, mirrors [CompressionLevel].
+/// Quality picks how many earlier matches get checked per byte, see
+/// [Level::search_depth].
+pub enum Level {
+    /// Naive lookback, quality between 1 and 10.
+    Naive { quality: usize },
+    /// Lookahead lookback, quality between 1 and 10.
+    Lookahead { quality: usize },
+    /// Makes the same matches as Nintendo's own encoder, so recompressing a
+    /// retail file gives it back byte for byte. Slow and single threaded.
+    Nintendo
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Level {
+    /// Quickest preset, what `compress-naive --level 1` uses.
+    pub const FASTEST: Self = Self::Naive { quality: 1 };
+    /// Default preset, what packing without a compression subcommand uses.
+    pub const DEFAULT: Self = Self::Lookahead { quality: 7 };
+    /// Smallest output preset, what `compress-look-ahead --level 10` uses.
+    pub const BEST: Self = Self::Lookahead { quality: 10 };
+
+    /// Most earlier matches checked per byte: 2 at quality 1 up to 1024 at
+    /// quality 10, out of range qualities are clamped. [Level::Nintendo]
+    /// checks all of them.
+    pub fn search_depth(&self) -> usize {
+        match *self {
+            Self::Naive { quality } | Self::Lookahead { quality } => 1 << quality.clamp(1, 10),
+            Self::Nintendo => usize::MAX
+        }
+    }
+}
+
+impl From<Level> for CompressionLevel {
+    fn from(value: Level) -> Self {
+        match value {
+            Level::Naive { quality } => Self::Naive { quality },
+            Level::Lookahead { quality } => Self::Lookahead { quality },
+            Level::Nintendo => Self::Lookahead { quality: 10 }
+        }
+    }
+}
+
+impl From<CompressionLevel> for Level {
+    fn from(value: CompressionLevel) -> Self {
+        match value {
+            CompressionLevel::Naive { quality } => Self::Naive { quality },
+            CompressionLevel::Lookahead { quality } => Self::Lookahead { quality }
+        }
+    }
+}
+
+/// Number of threads compression uses unless told otherwise.
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |x| x.get())
+}
+
+/// Checks if `data` is already a compressed stream.
+pub fn is_compressed_stream(data: &[u8]) -> bool {
+    data.starts_with(YAZ0_MAGIC) || data.starts_with(yay0::MAGIC)
+}
+
+/// Turns a single file's data into what gets stored in the Archive.
+/// Files flagged [FileAttr::COMPRESSED] are Yaz0 compressed if they're also
+/// flagged [FileAttr::USE_SZS] and Yay0 compressed otherwise, unless they
+/// already are.
+pub fn compress_payload(data: &[u8], attr: FileAttr, level: Level) -> RarcResult<Cow<'_, [u8]>> {
+    if !attr.contains(FileAttr::COMPRESSED) || is_compressed_stream(data) {
+        return Ok(Cow::Borrowed(data));
+    }
+    if !attr.contains(FileAttr::USE_SZS) {
+        return Ok(Cow::Owned(yay0::compress(data, level)));
+    }
+    Ok(Cow::Owned(szs::compress(data, level)))
+}
+
+/// Undoes [compress_payload], data that isn't compressed is returned as is.
+/// Corrupt or truncated Yaz0 and Yay0 streams are an error.
+pub fn decompress_payload(data: &[u8]) -> RarcResult<Cow<'_, [u8]>> {
+    if data.starts_with(yay0::MAGIC) {
+        return Ok(Cow::Owned(yay0::decompress(data)?));
+    }
+    if data.starts_with(szs::MAGIC) {
+        return Ok(Cow::Owned(szs::decompress(data)?));
+    }
+    Ok(Cow::Borrowed(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use binrw::Endian;
+
+    use super::*;
+    use crate::archive::UnpackOptions;
+    use crate::fixture::{read, sample, temp_dir};
+
+    const SZS: FileAttr = FileAttr(FileAttr::FILE.0 | FileAttr::COMPRESSED.0 | FileAttr::USE_SZS.0);
+
+    #[test]
+    fn payloads_round_trip() {
+        let data = b"abcabcabcabcabcabc".repeat(8);
+        let plain = compress_payload(&data, FileAttr::FILE, Level::default()).unwrap();
+        assert!(matches!(plain, Cow::Borrowed(_)));
+        let packed = compress_payload(&data, SZS, Level::default()).unwrap();
+        assert!(is_compressed_stream(&packed) && packed.len() < data.len());
+        assert_eq!(decompress_payload(&packed).unwrap(), data.as_slice());
+        // Already compressed data is stored as is.
+        assert_eq!(compress_payload(&packed, SZS, Level::default()).unwrap(), packed);
+        assert!(matches!(decompress_payload(&data).unwrap(), Cow::Borrowed(_)));
+        let yay0 = compress_payload(&data, FileAttr::FILE | FileAttr::COMPRESSED, Level::default()).unwrap();
+        assert!(yay0.starts_with(yay0::MAGIC));
+        assert_eq!(decompress_payload(&yay0).unwrap(), data.as_slice());
+    }
+
+    #[test]
+    fn compressed_files_pack_and_unpack() {
+        let mut archive = sample();
+        let file = archive.get_file("stage/jmp/b.bcsv").unwrap();
+        file.borrow_mut().attr |= FileAttr::COMPRESSED | FileAttr::USE_SZS;
+        archive.compression = Level::Naive { quality: 1 };
+        let reread = read(&archive.to_bytes(Endian::Big).unwrap());
+        let stored = reread.get_file("stage/jmp/b.bcsv").unwrap().borrow().data.clone();
+        assert!(is_compressed_stream(&stored));
+        assert_eq!(decompress_payload(&stored).unwrap(), [0xAB; 0x30].as_slice());
+
+        let dir = temp_dir("compressed_unpack");
+        let root = reread.unpack_with(&dir, UnpackOptions { decompress: true, ..Default::default() }).unwrap();
+        assert_eq!(std::fs::read(root.join("jmp/b.bcsv")).unwrap(), [0xAB; 0x30]);
+        assert_eq!(std::fs::read(root.join("a.bin")).unwrap(), b"first file");
+        let root = reread.unpack(dir.join("raw")).unwrap();
+        assert_eq!(std::fs::read(root.join("jmp/b.bcsv")).unwrap(), stored);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
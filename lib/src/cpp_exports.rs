@@ -1,4 +1,6 @@
+use std::io::Cursor;
 use cxx;
+use yaz0::CompressionLevel;
 use super::*;
 
 #[cxx::bridge(namespace = "librarc")]
@@ -10,7 +12,7 @@ mod ffi {
 }
 
 fn archive_to_dir(data: &cxx::CxxVector<u8>, output: &cxx::CxxString) -> String {
-    let mut reader = Cursor::new(decompres_yaz0(data.as_slice()));
+    let mut reader = Cursor::new(decompress(data.as_slice()));
     let mut archive = Archive::default();
     if let Ok(_) = archive.read(&mut reader) {
         let dir = output.to_string_lossy().into_owned();
@@ -0,0 +1,259 @@
+use crate::{Archive, Reference, nodes::*, FileAttr};
+use crate::error::{RarcError, RarcResult};
+use crate::lookup::Entry;
+
+/// Checks that `name` can be used for a single node.
+fn check_name(path: &str, name: &str) -> RarcResult<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(RarcError::InvalidOperation { path: path.into(), reason: "invalid node name" });
+    }
+    Ok(())
+}
+
+/// Checks that `dir` doesn't already contain a node called `name`, or one
+/// other than `node` whose name hashes the same.
+fn check_free(archive: &Archive, dir: &Reference<Directory>, name: &str, node: Option<&Reference<File>>)
+    -> RarcResult<()> {
+    let dir = dir.borrow();
+    if dir.children.iter().any(|x| x.borrow().name == name) {
+        return Err(RarcError::AlreadyExists { path: format!("{}/{}", dir, name) });
+    }
+    let hash = archive.name_hash(name);
+    if let Some(other) = dir.children.iter()
+        .find(|x| node.is_none_or(|y| !Reference::ptr_eq(x, y)) && archive.name_hash(&x.borrow().name) == hash) {
+        return Err(RarcError::HashCollision { path: format!("{}/{}", dir, name),
+            other: format!("{}/{}", dir, other.borrow().name), hash });
+    }
+    Ok(())
+}
+
+/// The folder a node lives in, [None] for the root.
+fn parent_of(entry: &Entry) -> Option<Reference<Directory>> {
+    match entry {
+        Entry::File(file) => file.borrow().get_parent(),
+        Entry::Dir(dir) => dir.borrow().get_file()
+            .and_then(|x| x.borrow().get_parent())
+    }
+}
+
+/// The node that represents `entry` inside its parent's children.
+fn node_of(entry: &Entry) -> Option<Reference<File>> {
+    match entry {
+        Entry::File(file) => Some(file.clone()),
+        Entry::Dir(dir) => dir.borrow().get_file()
+    }
+}
+
+/// Collects `dir` and every folder below it.
+fn collect_folders(vec: &mut Vec<Reference<Directory>>, dir: &Reference<Directory>) {
+    vec.push(dir.clone());
+    for child in &dir.borrow().children {
+        let child = child.borrow();
+        if !child.is_shortcut() && let Some(folder) = child.get_folder() {
+            collect_folders(vec, &folder);
+        }
+    }
+}
+
+impl Archive {
+    /// Adds a new file called `name` holding `data` inside the folder at `dir`.
+    pub fn add_file<A: AsRef<str>, B: AsRef<str>>(&mut self, dir: A, name: B, data: Vec<u8>,
+        attr: FileAttr) -> RarcResult<Reference<File>> {
+        let name = name.as_ref();
+        check_name(dir.as_ref(), name)?;
+        let parent = self.get_dir(dir)?;
+        check_free(self, &parent, name, None)?;
+        let file = self.create_file(name, attr | FileAttr::FILE, Some(parent));
+        file.borrow_mut().set_data(data);
+        self.sort()?;
+        Ok(file)
+    }
+    /// Adds a new, empty folder called `name` inside the folder at `dir`.
+    pub fn add_dir<A: AsRef<str>, B: AsRef<str>>(&mut self, dir: A, name: B)
+        -> RarcResult<Reference<Directory>> {
+        let name = name.as_ref();
+        check_name(dir.as_ref(), name)?;
+        let parent = self.get_dir(dir)?;
+        check_free(self, &parent, name, None)?;
+        let folder = self.create_folder(name, Some(parent));
+        self.sort()?;
+        Ok(folder)
+    }
+    /// Removes the node at `path`. Folders are removed with everything in them.
+    pub fn remove<A: AsRef<str>>(&mut self, path: A) -> RarcResult<()> {
+        let path = path.as_ref();
+        let entry = self.lookup(path)?;
+        let (Some(parent), Some(node)) = (parent_of(&entry), node_of(&entry)) else {
+            return Err(RarcError::InvalidOperation { path: path.into(), reason: "the root can't be removed" });
+        };
+        parent.borrow_mut().children.retain(|x| !Reference::ptr_eq(x, &node));
+        node.borrow_mut().parent = None;
+        if let Entry::Dir(dir) = &entry {
+            let mut removed = vec![];
+            collect_folders(&mut removed, dir);
+            self.folders.retain(|x| !removed.iter().any(|y| Reference::ptr_eq(x, y)));
+        }
+        self.sort()
+    }
+    /// Renames the node at `path` to `name`, keeping it in the same folder.
+    pub fn rename<A: AsRef<str>, B: AsRef<str>>(&mut self, path: A, name: B) -> RarcResult<()> {
+        let (path, name) = (path.as_ref(), name.as_ref());
+        check_name(path, name)?;
+        let entry = self.lookup(path)?;
+        if let (Some(parent), Some(node)) = (parent_of(&entry), node_of(&entry))
+            && node.borrow().name != name {
+            check_free(self, &parent, name, Some(&node))?;
+        }
+        if let Some(node) = node_of(&entry) {
+            node.borrow_mut().name = name.into();
+        }
+        if let Entry::Dir(dir) = &entry {
+            let mut dir = dir.borrow_mut();
+            dir.name = name.into();
+            dir.node.short_name = dir.short_name();
+        }
+        Ok(())
+    }
+    /// Moves the node at `path` into the folder at `dir`, with all its children.
+    pub fn move_to<A: AsRef<str>, B: AsRef<str>>(&mut self, path: A, dir: B) -> RarcResult<()> {
+        let path = path.as_ref();
+        let entry = self.lookup(path)?;
+        let dest = self.get_dir(dir)?;
+        let (Some(parent), Some(node)) = (parent_of(&entry), node_of(&entry)) else {
+            return Err(RarcError::InvalidOperation { path: path.into(), reason: "the root can't be moved" });
+        };
+        if let Entry::Dir(dir) = &entry {
+            let mut current = Some(dest.clone());
+            while let Some(folder) = current {
+                if Reference::ptr_eq(&folder, dir) {
+                    return Err(RarcError::InvalidOperation { path: path.into(),
+                        reason: "a folder can't be moved inside itself" });
+                }
+                current = parent_of(&Entry::Dir(folder));
+            }
+        }
+        if Reference::ptr_eq(&parent, &dest) {
+            return Ok(());
+        }
+        check_free(self, &dest, &node.borrow().name, None)?;
+        parent.borrow_mut().children.retain(|x| !Reference::ptr_eq(x, &node));
+        dest.borrow_mut().children.push(node.clone());
+        node.borrow_mut().parent = Some(dest.downgrade());
+        if let Entry::Dir(dir) = &entry {
+            for child in &dir.borrow().children {
+                let mut child = child.borrow_mut();
+                if child.is_shortcut() && child.name == ".." {
+                    child.folder = Some(dest.downgrade());
+                }
+            }
+        }
+        self.sort()
+    }
+    /// Replaces the contents of the file at `path`.
+    pub fn replace_data<A: AsRef<str>>(&mut self, path: A, data: Vec<u8>) -> RarcResult<()> {
+        let file = self.get_file(path)?;
+        file.borrow_mut().set_data(data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::sample;
+
+    /// The folder a "." or ".." node in `dir` points at.
+    fn shortcut(dir: &Reference<Directory>, name: &str) -> Option<Reference<Directory>> {
+        let dir = dir.borrow();
+        let node = dir.children.iter().find(|x| x.borrow().name == name).unwrap();
+        let node = node.borrow();
+        assert!(node.is_shortcut());
+        node.get_folder()
+    }
+
+    fn same(x: Option<Reference<Directory>>, y: &Reference<Directory>) -> bool {
+        x.is_some_and(|x| Reference::ptr_eq(&x, y))
+    }
+
+    /// Checks "." and ".." of every folder, and that every node's parent
+    /// really holds it.
+    fn check_tree(archive: &Archive) {
+        for folder in &archive.folders {
+            assert!(same(shortcut(folder, "."), folder));
+            match parent_of(&Entry::Dir(folder.clone())) {
+                Some(parent) => assert!(same(shortcut(folder, ".."), &parent)),
+                None => assert!(shortcut(folder, "..").is_none())
+            }
+            let dir = folder.borrow();
+            let shortcuts: Vec<_> = dir.children.iter().rev().take(2).map(|x| x.borrow().name.clone()).collect();
+            assert_eq!(shortcuts, ["..", "."], "shortcuts go last in {}", dir.name);
+            for child in dir.children.iter().filter(|x| !x.borrow().is_shortcut()) {
+                assert!(same(child.borrow().get_parent(), folder));
+            }
+        }
+    }
+
+    #[test]
+    fn shortcuts_and_parents() {
+        let mut archive = sample();
+        check_tree(&archive);
+        let model = archive.get_dir("stage/jmp/Model").unwrap();
+        assert!(same(shortcut(&model, ".."), &archive.get_dir("stage/jmp").unwrap()));
+        archive.move_to("stage/jmp/Model", "stage").unwrap();
+        check_tree(&archive);
+        assert!(same(shortcut(&model, ".."), &archive.root));
+        assert_eq!(archive.get_file("stage/Model/c.bin").unwrap().borrow().data, b"nested");
+        assert!(!archive.exists("stage/jmp/Model"));
+        archive.add_dir("stage/Model", "new").unwrap();
+        archive.rename("stage/Model", "Other").unwrap();
+        check_tree(&archive);
+        assert!(archive.exists("stage/Other/new/.."));
+        archive.replace_data("stage/Other/c.bin", vec![9; 3]).unwrap();
+        assert_eq!(archive.get_file("stage/Other/c.bin").unwrap().borrow().data, [9; 3]);
+    }
+
+    #[test]
+    fn ids_after_remove() {
+        let mut archive = sample();
+        archive.remove("stage/jmp").unwrap();
+        check_tree(&archive);
+        assert_eq!(archive.folders.len(), 1);
+        assert!(!archive.exists("stage/jmp/b.bcsv"));
+        for (i, file) in archive.files.iter().enumerate() {
+            let file = file.borrow();
+            if file.is_file() {
+                assert_eq!(file.node.id as usize, i, "synced ids are node indices");
+            }
+        }
+        assert_eq!(archive.next_id() as usize, archive.files.len());
+
+        let mut archive = sample();
+        *archive.sync_mut() = false;
+        archive.remove("stage/a.bin").unwrap();
+        let ids: Vec<_> = archive.files.iter()
+            .map(|x| x.borrow())
+            .filter(|x| x.is_file())
+            .map(|x| (x.name.clone(), x.node.id))
+            .collect();
+        assert_eq!(ids.iter().map(|x| x.1).collect::<Vec<_>>(), (0..ids.len() as u16).collect::<Vec<_>>());
+        assert!(ids.iter().all(|x| x.0 != "a.bin"));
+        assert_eq!(archive.next_id() as usize, ids.len());
+    }
+
+    #[test]
+    fn bad_edits_fail() {
+        let mut archive = sample();
+        for dest in ["stage/jmp", "stage/jmp/Model"] {
+            assert!(matches!(archive.move_to("stage/jmp", dest), Err(RarcError::InvalidOperation { .. })));
+        }
+        assert!(matches!(archive.move_to("stage", "stage/jmp"), Err(RarcError::InvalidOperation { .. })));
+        assert!(matches!(archive.remove("stage"), Err(RarcError::InvalidOperation { .. })));
+        assert!(matches!(archive.add_file("stage", "a.bin", vec![], FileAttr::LOAD_TO_MRAM),
+            Err(RarcError::AlreadyExists { .. })));
+        for name in [".", "..", "", "x/y"] {
+            assert!(matches!(archive.rename("stage/a.bin", name), Err(RarcError::InvalidOperation { .. })));
+        }
+        check_tree(&archive);
+        assert!(archive.exists("stage/jmp/Model/c.bin"));
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+
+use crate::table::NameEncoding;
+
+/// Every error an Archive operation can produce. Carries the offset or node
+/// index that caused it whenever one is known.
+#[derive(Debug)]
+pub enum RarcError {
+    /// The magic at `offset` isn't "RARC" or "CRAR".
+    BadMagic { offset: u64, magic: [u8; 4] },
+    /// A section ended before it could be fully read.
+    Truncated { section: &'static str, offset: u64 },
+    /// A node's name offset doesn't point at a string in the table.
+    BadNameOffset { node: usize, offset: u32 },
+    /// A folder file node points to a directory that doesn't exist.
+    BadDirectoryIndex { node: usize, index: u32 },
+    /// A directory's children run past the end of the file nodes.
+    BadFileIndex { node: usize, index: u32 },
+    /// A directory isn't registered in [crate::Archive::folders].
+    UnlinkedDirectory { name: String },
+    /// A string at `offset` in the string table couldn't be decoded.
+    InvalidEncoding { offset: u32 },
+    /// A node name has chars the Archive's name encoding can't hold.
+    UnencodableName { name: String, encoding: NameEncoding },
+    /// Nothing exists at this in-archive path.
+    NotFound { path: String },
+    /// A file was wanted but the path is a directory.
+    IsADirectory { path: String },
+    /// A directory was wanted, or walked through, but the path is a file.
+    NotADirectory { path: String },
+    /// Something already exists at this in-archive path.
+    AlreadyExists { path: String },
+    /// Two nodes in the same folder share a name hash, see
+    /// [crate::Archive::folder_collisions].
+    HashCollision { path: String, other: String, hash: u16 },
+    /// The edit at this path would break the Archive.
+    InvalidOperation { path: String, reason: &'static str },
+    /// A glob pattern couldn't be compiled.
+    BadPattern { pattern: String, message: &'static str },
+    /// A compressed stream points outside of what it already decompressed.
+    BadCompressedData { format: &'static str, offset: u64 },
+    /// Something in the layout starts at `offset`, before the previous thing
+    /// ended, so it can't be written front to back.
+    Overlap { offset: u64 },
+    /// A Yaz0 stream couldn't be compressed or decompressed.
+    Yaz0(yaz0::Error),
+    /// A sidecar manifest couldn't be read or written.
+    Manifest(serde_json::Error),
+    /// Any other binrw parse error.
+    Parse(binrw::Error),
+    /// Underlying I/O error.
+    Io(std::io::Error)
+}
+
+/// Result typedef used by every public Archive operation.
+pub type RarcResult<T> = Result<T, RarcError>;
+
+impl RarcError {
+    /// Builds a closure that turns a binrw error from reading `section` into
+    /// a [RarcError::Truncated] if the reader ran out of data.
+    pub(crate) fn section(section: &'static str, offset: u64) -> impl FnOnce(binrw::Error) -> Self {
+        move |err| match err.is_eof() {
+            true => Self::Truncated { section, offset },
+            false => err.into()
+        }
+    }
+}
+
+impl fmt::Display for RarcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic { offset, magic } =>
+                write!(f, "bad magic {:?} at offset {:#x}", magic.escape_ascii().to_string(), offset),
+            Self::Truncated { section, offset } =>
+                write!(f, "{} at offset {:#x} is truncated", section, offset),
+            Self::BadNameOffset { node, offset } =>
+                write!(f, "node {} has name offset {:#x} outside the string table", node, offset),
+            Self::BadDirectoryIndex { node, index } =>
+                write!(f, "node {} points to directory {} which doesn't exist", node, index),
+            Self::BadFileIndex { node, index } =>
+                write!(f, "directory {} points to file node {} which doesn't exist", node, index),
+            Self::UnlinkedDirectory { name } =>
+                write!(f, "directory {:?} isn't part of the archive", name),
+            Self::InvalidEncoding { offset } =>
+                write!(f, "string at table offset {:#x} has an invalid encoding", offset),
+            Self::UnencodableName { name, encoding } =>
+                write!(f, "{:?} can't be encoded as {:?}", name, encoding),
+            Self::NotFound { path } => write!(f, "{:?} doesn't exist", path),
+            Self::IsADirectory { path } => write!(f, "{:?} is a directory", path),
+            Self::NotADirectory { path } => write!(f, "{:?} is not a directory", path),
+            Self::AlreadyExists { path } => write!(f, "{:?} already exists", path),
+            Self::HashCollision { path, other, hash } =>
+                write!(f, "{:?} and {:?} are in the same folder and both hash to {:#06x}", path, other, hash),
+            Self::InvalidOperation { path, reason } => write!(f, "{:?}: {}", path, reason),
+            Self::BadPattern { pattern, message } =>
+                write!(f, "bad pattern {:?}: {}", pattern, message),
+            Self::BadCompressedData { format, offset } =>
+                write!(f, "{} stream is corrupt at offset {:#x}", format, offset),
+            Self::Overlap { offset } =>
+                write!(f, "layout overlaps itself at offset {:#x}", offset),
+            Self::Yaz0(err) => write!(f, "yaz0: {}", err),
+            Self::Manifest(err) => write!(f, "manifest: {}", err),
+            Self::Parse(err) => write!(f, "{}", err),
+            Self::Io(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl std::error::Error for RarcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Yaz0(err) => Some(err),
+            Self::Manifest(err) => Some(err),
+            Self::Parse(err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<std::io::Error> for RarcError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<yaz0::Error> for RarcError {
+    fn from(value: yaz0::Error) -> Self {
+        match value {
+            yaz0::Error::Io(e) => Self::Io(e),
+            err => Self::Yaz0(err)
+        }
+    }
+}
+
+impl From<serde_json::Error> for RarcError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Manifest(value)
+    }
+}
+
+impl From<binrw::Error> for RarcError {
+    fn from(value: binrw::Error) -> Self {
+        match value {
+            binrw::Error::Io(e) => Self::Io(e),
+            // Node writers fail with their own errors, see NameEncoding::encode_at.
+            binrw::Error::Custom { pos, err } => match err.downcast::<RarcError>() {
+                Ok(err) => *err,
+                Err(err) => Self::Parse(binrw::Error::Custom { pos, err })
+            },
+            err => Self::Parse(err)
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Archives shared by the unit tests.
+
+use std::io::Cursor;
+
+use binrw::Endian;
+
+use crate::{Archive, FileAttr, Reference, nodes::Directory};
+
+fn add_file(archive: &mut Archive, dir: &Reference<Directory>, name: &str, data: &[u8], attr: FileAttr) {
+    let file = archive.create_file(name, FileAttr::FILE | attr, Some(dir.clone()));
+    let mut file = file.borrow_mut();
+    file.data = data.into();
+    file.node.data_size = data.len() as u32;
+}
+
+/// `stage` holding `a.bin`, `jmp/b.bcsv`, `jmp/Model/c.bin` and `d.bin`,
+/// `b.bcsv` loading to ARAM and `c.bin` from DVD.
+pub(crate) fn sample() -> Archive {
+    let mut archive = Archive::create("stage", true);
+    let root = archive.root.clone();
+    add_file(&mut archive, &root, "a.bin", b"first file", FileAttr::LOAD_TO_MRAM);
+    let jmp = archive.create_folder("jmp", Some(root.clone()));
+    add_file(&mut archive, &jmp, "b.bcsv", &[0xAB; 0x30], FileAttr::LOAD_TO_ARAM);
+    let model = archive.create_folder("Model", Some(jmp));
+    add_file(&mut archive, &model, "c.bin", b"nested", FileAttr::LOAD_FROM_DVD);
+    add_file(&mut archive, &root, "d.bin", &[4; 5], FileAttr::LOAD_TO_MRAM);
+    archive.sort().unwrap();
+    archive
+}
+
+/// [sample] written as RARC.
+pub(crate) fn sample_bytes() -> Vec<u8> {
+    sample().to_bytes(Endian::Big).unwrap()
+}
+
+/// Reads an Archive that has to be valid.
+pub(crate) fn read(bytes: &[u8]) -> Archive {
+    let mut archive = Archive::default();
+    archive.read(&mut Cursor::new(bytes)).unwrap();
+    archive
+}
+
+/// Overwrites the big endian u32 at `off`.
+pub(crate) fn put_u32(bytes: &mut [u8], off: usize, value: u32) {
+    bytes[off..off + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// An empty dir only this test uses, under the system temp dir.
+pub(crate) fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("rarc_lib_{}_{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
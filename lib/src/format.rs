@@ -0,0 +1,76 @@
+use crate::{sarc, szs, u8, yay0};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What a buffer holds, going by its magic.
+pub enum Format {
+    /// A Yaz0 (SZS) stream, with its header.
+    Yaz0(szs::Header),
+    /// A Yay0 (SZP) stream.
+    Yay0,
+    /// A big endian Archive (GCN, Wii).
+    Rarc,
+    /// A little endian Archive (Switch).
+    Crar,
+    /// A Wii U8 archive.
+    U8,
+    /// A SARC archive (Wii U, Switch), either endian.
+    Sarc,
+    Unknown
+}
+
+impl Format {
+    /// Identifies `data` by its magic. A Yaz0 stream too short to hold its
+    /// header is [Format::Unknown].
+    pub fn detect(data: &[u8]) -> Self {
+        match data.get(..4) {
+            Some(magic) if magic == szs::MAGIC => szs::Header::read(data).map_or(Self::Unknown, Self::Yaz0),
+            Some(magic) if magic == yay0::MAGIC => Self::Yay0,
+            Some(b"RARC") => Self::Rarc,
+            Some(b"CRAR") => Self::Crar,
+            Some(magic) if magic == u8::MAGIC => Self::U8,
+            Some(magic) if magic == sarc::MAGIC => Self::Sarc,
+            _ => Self::Unknown
+        }
+    }
+    /// Checks if this is a compressed stream wrapping something else.
+    pub const fn is_compressed(&self) -> bool {
+        matches!(self, Self::Yaz0(_) | Self::Yay0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use binrw::Endian;
+    use super::*;
+    use crate::compression::Level;
+    use crate::fixture::sample;
+
+    #[test]
+    fn archives_are_detected() {
+        assert_eq!(Format::detect(&sample().to_bytes(Endian::Big).unwrap()), Format::Rarc);
+        assert_eq!(Format::detect(&sample().to_bytes(Endian::Little).unwrap()), Format::Crar);
+        let mut u8 = vec![];
+        sample().write_u8(&mut u8).unwrap();
+        assert_eq!(Format::detect(&u8), Format::U8);
+        for endian in [Endian::Big, Endian::Little] {
+            let mut sarc = vec![];
+            sample().write_sarc(&mut sarc, endian).unwrap();
+            assert_eq!(Format::detect(&sarc), Format::Sarc);
+        }
+        assert_eq!(Format::detect(b"PK\x03\x04"), Format::Unknown);
+        assert_eq!(Format::detect(b"RA"), Format::Unknown);
+    }
+
+    #[test]
+    fn wrapped_archives_are_detected() {
+        let bytes = sample().to_bytes(Endian::Big).unwrap();
+        let header = szs::Header { size: bytes.len() as u32, alignment: 0 };
+        let yaz0 = szs::compress(&bytes, Level::FASTEST);
+        assert_eq!(Format::detect(&yaz0), Format::Yaz0(header));
+        assert!(Format::detect(&yaz0).is_compressed());
+        assert_eq!(Format::detect(&yay0::compress(&bytes, Level::FASTEST)), Format::Yay0);
+        // Too short for its header.
+        assert_eq!(Format::detect(&yaz0[..8]), Format::Unknown);
+        assert!(!Format::Rarc.is_compressed());
+    }
+}
@@ -1,5 +1,6 @@
 use binrw::prelude::*;
 use binrw::Endian;
+use crate::error::{RarcError, RarcResult};
 
 #[binrw]
 #[brw(repr = u32)]
@@ -58,16 +59,45 @@ pub struct DataHeader {
     // Make bool binrw compatible.
     #[br(map = |x: u8| x != 0)]
     #[bw(map = |x: &bool| *x as u8)]
-    #[brw(pad_after = 5)]
-    pub sync: bool
+    pub sync: bool,
+    /// Usually zero, kept so a read Archive can be written back unchanged.
+    pub padding: [u8; 5]
 }
 
 /// Utility method to read the headers, also provides the Endian for later usage.
-pub fn read_headers<R: BinReaderExt>(reader: &mut R) -> BinResult<(Endian, Header, DataHeader)> {
-    let magic = Magic::read_ne(reader)?;
+pub fn read_headers<R: BinReaderExt>(reader: &mut R) -> RarcResult<(Endian, Header, DataHeader)> {
+    let start = reader.stream_position()?;
+    let magic = match Magic::read_ne(reader) {
+        Ok(magic) => magic,
+        Err(binrw::Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            return Err(RarcError::Truncated { section: "header", offset: start }),
+        Err(binrw::Error::Io(e)) => return Err(e.into()),
+        Err(_) => {
+            reader.seek(std::io::SeekFrom::Start(start))?;
+            let magic = <[u8; 4]>::read_ne(reader)?;
+            return Err(RarcError::BadMagic { offset: start, magic });
+        }
+    };
     let endian = magic.to_endian();
-    let header: Header = reader.read_type(endian)?;
-    reader.seek(std::io::SeekFrom::Start(header.data_header_off as _))?;
-    let data_header = reader.read_type(endian)?;
+    let header: Header = reader.read_type(endian)
+        .map_err(RarcError::section("header", start))?;
+    let off = header.data_header_off as u64;
+    reader.seek(std::io::SeekFrom::Start(off))?;
+    let data_header = reader.read_type(endian)
+        .map_err(RarcError::section("data header", off))?;
     Ok((endian, header, data_header))
-}
\ No newline at end of file
+}
+/// Checks that the directory and file nodes the headers count fit in a
+/// `stream_len` bytes long input, so their counts are safe to allocate for.
+pub(crate) fn check_node_counts(header: &Header, data_header: &DataHeader, stream_len: u64) -> RarcResult<()> {
+    let base = header.data_header_off as u64;
+    for (section, off, size) in [
+        ("directory nodes", data_header.dir_node_off, data_header.dir_node_count as u64 * 0x10),
+        ("file nodes", data_header.file_node_off, data_header.file_node_count as u64 * 0x14)
+    ] {
+        if base + off as u64 + size > stream_len {
+            return Err(RarcError::Truncated { section, offset: base + off as u64 });
+        }
+    }
+    Ok(())
+}
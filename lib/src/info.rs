@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use crate::FileAttr;
+use crate::lazy::Index;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// How many files load from a segment and how much space it takes.
+pub struct Segment {
+    pub files: u32,
+    /// From the [crate::header::Header], padding included.
+    pub size: u32
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// Where an Archive's bytes go, see [Index::stats].
+pub struct Stats {
+    /// Directory nodes, the root included.
+    pub folders: u32,
+    /// File nodes holding data.
+    pub files: u32,
+    /// File nodes pointing to a folder, "." and ".." included.
+    pub folder_nodes: u32,
+    pub mram: Segment,
+    pub aram: Segment,
+    pub dvd: Segment,
+    /// Every file's data added up, as if none of it was shared.
+    pub data_bytes: u64,
+    /// The data actually stored, shared data counted once.
+    pub unique_bytes: u64,
+    /// Bytes saved by files sharing data.
+    pub dedup_saved: u64,
+    /// Padding between and after file data.
+    pub data_padding: u64,
+    /// Padding after the directory and file nodes.
+    pub node_padding: u64,
+    /// Padding after the string table.
+    pub table_padding: u64,
+    /// Everything in the Archive that isn't headers, nodes, names or data.
+    pub total_padding: u64
+}
+
+impl Index {
+    /// Counts nodes and adds up data and padding, all from the headers and
+    /// nodes without touching file data.
+    pub fn stats(&self) -> Stats {
+        let Index { header, data_header, .. } = self;
+        let mut stats = Stats {
+            folders: self.folders.len() as u32,
+            mram: Segment { size: header.mram_size, ..Default::default() },
+            aram: Segment { size: header.aram_size, ..Default::default() },
+            dvd: Segment { size: header.dvd_size, ..Default::default() },
+            ..Default::default()
+        };
+        let mut ranges = HashMap::new();
+        for file in &self.files {
+            if file.is_dir() {
+                stats.folder_nodes += 1;
+                continue;
+            }
+            if !file.is_file() {
+                continue;
+            }
+            stats.files += 1;
+            if file.attr.contains(FileAttr::LOAD_TO_MRAM) {
+                stats.mram.files += 1;
+            } else if file.attr.contains(FileAttr::LOAD_TO_ARAM) {
+                stats.aram.files += 1;
+            } else if file.attr.contains(FileAttr::LOAD_FROM_DVD) {
+                stats.dvd.files += 1;
+            }
+            let size = file.node.data_size as u64;
+            stats.data_bytes += size;
+            let stored = ranges.entry(file.node.data).or_insert(0);
+            *stored = size.max(*stored);
+        }
+        stats.unique_bytes = ranges.values().sum();
+        stats.dedup_saved = stats.data_bytes - stats.unique_bytes;
+        stats.data_padding = (header.file_data_len as u64).saturating_sub(stats.unique_bytes);
+        let dir_nodes = data_header.dir_node_off as u64 + data_header.dir_node_count as u64 * 0x10;
+        let file_nodes = data_header.file_node_off as u64 + data_header.file_node_count as u64 * 0x14;
+        stats.node_padding = (data_header.file_node_off as u64).saturating_sub(dir_nodes)
+            + (data_header.string_tbl_off as u64).saturating_sub(file_nodes);
+        stats.table_padding = data_header.string_tbl_size.saturating_sub(self.strings) as u64;
+        let used = 0x20 + 0x20
+            + data_header.dir_node_count as u64 * 0x10
+            + data_header.file_node_count as u64 * 0x14
+            + self.strings as u64
+            + stats.unique_bytes;
+        stats.total_padding = (header.size as u64).saturating_sub(used);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use binrw::Endian;
+
+    use crate::fixture::sample;
+    use crate::lazy::ArchiveView;
+    use crate::table::NameEncoding;
+    use super::*;
+
+    #[test]
+    fn stats_add_up() {
+        let archive = sample();
+        archive.get_file("stage/d.bin").unwrap().borrow_mut().set_data(b"first file".to_vec());
+        let bytes = archive.to_bytes(Endian::Big).unwrap();
+        let view = ArchiveView::new(&bytes, NameEncoding::Utf8).unwrap();
+        let stats = view.index.stats();
+        assert_eq!((stats.folders, stats.files, stats.folder_nodes), (3, 4, 8));
+        assert_eq!(stats.mram, Segment { files: 2, size: 0x20 });
+        assert_eq!(stats.aram, Segment { files: 1, size: 0x40 });
+        assert_eq!(stats.dvd, Segment { files: 1, size: 0x20 });
+        assert_eq!((stats.data_bytes, stats.unique_bytes, stats.dedup_saved), (74, 64, 10));
+        assert_eq!(stats.data_padding, 0x80 - 64);
+        let nodes = 0x20 + 0x20 + 3 * 0x10 + 12 * 0x14;
+        let strings = view.index.data_header.string_tbl_size as u64 - stats.table_padding;
+        assert_eq!(nodes + strings + stats.unique_bytes + stats.total_padding, bytes.len() as u64);
+    }
+}
@@ -1,4 +1,26 @@
 use crate::{Archive, Reference, nodes::*};
+use crate::error::{RarcError, RarcResult};
+use glob::{MatchOptions, Pattern};
+
+/// Options used for every in-archive glob, `*` never crosses a `/`.
+pub(crate) const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false
+};
+
+/// Generates the in-archive path of a File, always separated by `/`.
+pub(crate) fn archive_path(file: &File) -> String {
+    file.to_string().replace(std::path::MAIN_SEPARATOR, "/")
+}
+
+/// Compiles `pattern` into a glob, reporting why it failed if it can't.
+pub(crate) fn compile_glob(pattern: &str) -> RarcResult<Pattern> {
+    Pattern::new(pattern).map_err(|e| RarcError::BadPattern {
+        pattern: pattern.into(),
+        message: e.msg
+    })
+}
 
 pub struct DirIter<'a> {
     archive: &'a Archive,
@@ -22,11 +44,14 @@ impl<'a> DirIter<'a> {
         if &borrow.name == name {
             vec.push(node.clone());
         }
-        for child in &borrow.children {
-            let cborrow = child.borrow();
-            if let Some(dir) = &cborrow.folder {
-                Self::match_name(vec, dir, name);
-            }
+        let folders: Vec<_> = borrow.children.iter()
+            .map(|x| x.borrow())
+            .filter(|x| !x.is_shortcut())
+            .filter_map(|x| x.get_folder())
+            .collect();
+        drop(borrow);
+        for dir in folders {
+            Self::match_name(vec, &dir, name);
         }
     }
 }
@@ -48,21 +73,58 @@ impl<'a> FileIter<'a> {
     }
     fn match_name(vec: &mut Vec<Reference<File>>, node: &Reference<Directory>,
         name: &String) {
-        let borrow = node.borrow();
-        for child in &borrow.children {
+        let children = node.borrow().children.clone();
+        for child in &children {
             let cborrow = child.borrow();
             if cborrow.is_file() {
                 if &cborrow.name == name {
                     vec.push(child.clone());
                 }
-            } else if let Some(folder) 
-                = &cborrow.folder {
-                Self::match_name(vec, folder, name);
+            } else if !cborrow.is_shortcut() && let Some(folder) 
+                = cborrow.get_folder() {
+                drop(cborrow);
+                Self::match_name(vec, &folder, name);
             }
         }
     }
 }
 
+pub struct GlobIter<'a> {
+    archive: &'a Archive,
+    pattern: Pattern
+}
+
+impl<'a> GlobIter<'a> {
+    pub fn new<A: AsRef<str>>(archive: &'a Archive, pattern: A) -> RarcResult<Self> {
+        let pattern = compile_glob(pattern.as_ref())?;
+        Ok(Self {archive, pattern})
+    }
+    /// Every file and folder node whose full path matches, in tree order.
+    pub fn find_matches(&self) -> Vec<Reference<File>> {
+        let mut result = vec![];
+        let GlobIter { archive, pattern } = self;
+        Self::match_path(&mut result, &archive.root, pattern);
+        result
+    }
+    fn match_path(vec: &mut Vec<Reference<File>>, node: &Reference<Directory>,
+        pattern: &Pattern) {
+        // archive_path locks `node` again, so don't hold it meanwhile.
+        let children = node.borrow().children.clone();
+        for child in &children {
+            let cborrow = child.borrow();
+            if cborrow.is_shortcut() {
+                continue;
+            }
+            if pattern.matches_with(&archive_path(&cborrow), GLOB_OPTIONS) {
+                vec.push(child.clone());
+            }
+            if let Some(folder) = cborrow.get_folder() {
+                drop(cborrow);
+                Self::match_path(vec, &folder, pattern);
+            }
+        }
+    }
+}
 
 impl Archive {
     pub fn find_dirs_by_name<A: AsRef<str>>(&self, name: A) -> Vec<Reference<Directory>> {
@@ -73,4 +135,42 @@ impl Archive {
         let iter = FileIter::new(self, name);
         iter.find_matches()
     }
-}
\ No newline at end of file
+    /// Finds every node whose full path (starting with the root's name)
+    /// matches the glob `pattern`, e.g. `stage/jmp/Placement/**/*.bcsv`.
+    pub fn find_by_glob<A: AsRef<str>>(&self, pattern: A) -> RarcResult<Vec<Reference<File>>> {
+        let iter = GlobIter::new(self, pattern)?;
+        Ok(iter.find_matches())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{sample, temp_dir};
+
+    fn paths(archive: &Archive, pattern: &str) -> Vec<String> {
+        archive.find_by_glob(pattern).unwrap().iter().map(|x| archive_path(&x.borrow())).collect()
+    }
+
+    #[test]
+    fn globs_match_full_paths() {
+        let archive = sample();
+        assert_eq!(paths(&archive, "stage/*.bin"), ["stage/a.bin", "stage/d.bin"]);
+        assert_eq!(paths(&archive, "stage/**/*.bin"), ["stage/a.bin", "stage/jmp/Model/c.bin", "stage/d.bin"]);
+        assert_eq!(paths(&archive, "stage/jmp"), ["stage/jmp"]);
+        assert!(paths(&archive, "*.bin").is_empty());
+        assert!(matches!(archive.find_by_glob("stage/[a"), Err(RarcError::BadPattern { .. })));
+    }
+
+    #[test]
+    fn extract_writes_matches_only() {
+        let archive = sample();
+        let dir = temp_dir("extract");
+        let written = archive.extract(&["stage/jmp", "stage/jmp/Model/*", "stage/d.bin"], &dir).unwrap();
+        assert_eq!(written, [dir.join("stage/jmp"), dir.join("stage/d.bin")]);
+        assert_eq!(std::fs::read(dir.join("stage/jmp/Model/c.bin")).unwrap(), b"nested");
+        assert_eq!(std::fs::read(dir.join("stage/d.bin")).unwrap(), [4; 5]);
+        assert!(!dir.join("stage/a.bin").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
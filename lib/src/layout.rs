@@ -0,0 +1,387 @@
+use std::{borrow::Cow, collections::HashMap, io::{Cursor, Read, Write}};
+use std::hash::{DefaultHasher, Hasher};
+
+use binrw::prelude::*;
+
+use crate::{Archive, FileAttr, Reference};
+use crate::compression::{Level, compress_payload};
+use crate::error::{RarcError, RarcResult};
+use crate::header::{DataHeader, Header, Magic};
+use crate::nodes::File;
+use crate::table::Table;
+
+#[derive(Debug, Clone)]
+/// Every offset and size of an Archive, worked out before anything is written
+/// so [Archive::write_layout] only ever has to write forward.
+pub struct Layout {
+    pub header: Header,
+    pub data_header: DataHeader,
+    /// Every string is written at its key, from [DataHeader::string_tbl_off].
+    pub table: Table,
+    /// Nodes are written exactly as read instead of regenerated, see
+    /// [crate::preserve::PreservedLayout].
+    pub preserved: bool,
+    /// Where each File's data goes. Files sharing data only show up once.
+    pub data: Vec<Placement>,
+    /// Size of the whole Archive once written.
+    pub size: u64
+}
+
+#[derive(Debug, Clone)]
+/// A File's data as placed in a [Layout].
+pub struct Placement {
+    /// From the start of the Archive.
+    pub offset: u64,
+    pub file: Reference<File>
+}
+
+/// A piece of the Archive, in the order it's written.
+enum Section {
+    Bytes(Vec<u8>),
+    Data(Placement)
+}
+
+/// Tracks how far a plain [Write] got, so gaps can be zero filled.
+struct Sequential<'a, W: Write> {
+    writer: &'a mut W,
+    pos: u64
+}
+
+impl<W: Write> Sequential<'_, W> {
+    /// Zero fills up to `offset`, which can't be behind what was written.
+    fn skip_to(&mut self, offset: u64) -> RarcResult<()> {
+        if offset < self.pos {
+            return Err(RarcError::Overlap { offset });
+        }
+        let zeros = [0u8; 0x1000];
+        while self.pos < offset {
+            let len = (offset - self.pos).min(zeros.len() as u64) as usize;
+            self.write_all(&zeros[..len])?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Sequential<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Archive {
+    /// Works out where everything goes without writing anything, updating
+    /// every node's name and data offsets like [Archive::write] does.
+    /// Files flagged [FileAttr::COMPRESSED] get compressed here to size them,
+    /// then again as they're written so only one is ever held at a time.
+    /// Names sharing a hash are laid out as they are, see
+    /// [Archive::folder_collisions] or [crate::verify::verify] to catch them.
+    pub fn layout(&self) -> RarcResult<Layout> {
+        self.check_names()?;
+        if let Some(preserved) = &self.preserved
+            && preserved.matches(self) {
+            return Ok(self.preserved_layout(preserved));
+        }
+        let mut mram = vec![];
+        let mut aram = vec![];
+        let mut dvd = vec![];
+        for file in &self.files {
+            let fref = file.borrow();
+            if fref.attr.contains(FileAttr::LOAD_TO_MRAM) {
+                mram.push(file.clone());
+            } else if fref.attr.contains(FileAttr::LOAD_TO_ARAM) {
+                aram.push(file.clone());
+            } else if fref.attr.contains(FileAttr::LOAD_FROM_DVD) {
+                dvd.push(file.clone());
+            }
+        }
+        let dnodecount = self.folders.len() as u32;
+        let fnodecount = self.files.len() as u32;
+        let dnodeoff = 0x40 +
+            u32::next_multiple_of(dnodecount * 0x10, 32);
+        let stroff = dnodeoff +
+            u32::next_multiple_of(fnodecount * 0x14, 32);
+        let table = self.gen_table();
+        let fdatastart = (stroff + table.total_size()) as u64;
+        let mut data = vec![];
+        let mut total_size = 0;
+        let mut sizes = [0u32; 3];
+        for (files, size) in [mram, aram, dvd].into_iter().zip(&mut sizes) {
+            *size = layout_file_data(files, fdatastart, total_size, self.compression, &mut data)?;
+            total_size += *size;
+        }
+        let [mram_size, aram_size, dvd_size] = sizes;
+        let size = fdatastart + total_size as u64;
+        let header = Header {
+            size: size as u32,
+            data_header_off: 0x20,
+            file_data_off: fdatastart as u32 - 0x20,
+            file_data_len: total_size,
+            mram_size,
+            aram_size,
+            dvd_size
+        };
+        let data_header = DataHeader {
+            dir_node_count: dnodecount,
+            dir_node_off: 0x20,
+            file_node_count: fnodecount,
+            file_node_off: dnodeoff - 0x20,
+            string_tbl_size: table.total_size(),
+            string_tbl_off: stroff - 0x20,
+            next_idx: self.next_id(),
+            sync: self.sync(),
+            padding: self.data_header.padding
+        };
+        Ok(Layout { header, data_header, table, preserved: false, data, size })
+    }
+
+    /// Writes this Archive as `layout` says, strictly front to back. `layout`
+    /// must come from [Archive::layout] with no edits made since.
+    pub fn write_layout<W: Write>(&self, writer: &mut W, endian: binrw::Endian, layout: &Layout)
+        -> RarcResult<()> {
+        let base = layout.header.data_header_off as u64;
+        let mut sections = vec![];
+        let mut head = Cursor::new(vec![]);
+        Magic::from_endian(endian).write_ne(&mut head)?;
+        layout.header.write_options(&mut head, endian, ())?;
+        sections.push((0, Section::Bytes(head.into_inner())));
+        let mut data_header = Cursor::new(vec![]);
+        layout.data_header.write_options(&mut data_header, endian, ())?;
+        sections.push((base, Section::Bytes(data_header.into_inner())));
+        let mut folders = Cursor::new(vec![]);
+        for folder in &self.folders {
+            let folder = folder.borrow();
+            match layout.preserved {
+                true => folder.node.write_options(&mut folders, endian, ())?,
+                false => folder.write_encoded(&mut folders, endian, self.encoding)?
+            }
+        }
+        let off = base + layout.data_header.dir_node_off as u64;
+        sections.push((off, Section::Bytes(folders.into_inner())));
+        let mut files = Cursor::new(vec![]);
+        for file in &self.files {
+            let file = file.borrow();
+            match layout.preserved {
+                true => file.node.write_options(&mut files, endian, ())?,
+                false => file.write_encoded(&mut files, endian, self.encoding)?
+            }
+            0u32.write_ne(&mut files)?;
+        }
+        let off = base + layout.data_header.file_node_off as u64;
+        sections.push((off, Section::Bytes(files.into_inner())));
+        let table = base + layout.data_header.string_tbl_off as u64;
+        for (off, str) in layout.table.iter() {
+            let mut bytes = layout.table.encoded(str).into_owned();
+            bytes.push(0);
+            sections.push((table + *off as u64, Section::Bytes(bytes)));
+        }
+        for placement in &layout.data {
+            sections.push((placement.offset, Section::Data(placement.clone())));
+        }
+        sections.sort_by_key(|x| x.0);
+        let mut writer = Sequential { writer, pos: 0 };
+        for (off, section) in sections {
+            writer.skip_to(off)?;
+            match section {
+                Section::Bytes(bytes) => writer.write_all(&bytes)?,
+                Section::Data(placement) => {
+                    let file = placement.file.borrow();
+                    let size = file.node.data_size as u64;
+                    let payload = Payload::new(&file, self.compression)?;
+                    if std::io::copy(&mut payload.open()?.take(size), &mut writer)? != size {
+                        return Err(RarcError::Truncated { section: "file data", offset: off });
+                    }
+                }
+            }
+        }
+        writer.skip_to(layout.size)?;
+        Ok(())
+    }
+}
+
+/// Compresses `file` if it's flagged [FileAttr::COMPRESSED] and isn't yet,
+/// [None] if its own data is stored as is.
+fn compress(file: &File, level: Level) -> RarcResult<Option<Vec<u8>>> {
+    if !file.is_compressed() {
+        return Ok(None);
+    }
+    let data = file.read_data()?;
+    match compress_payload(&data, file.attr, level)? {
+        Cow::Borrowed(_) if file.source.is_none() => Ok(None),
+        data => Ok(Some(data.into_owned()))
+    }
+}
+
+/// What a File is stored as: its compressed data if it has to be compressed,
+/// otherwise streamed from wherever its data lives.
+enum Payload<'a> {
+    Memory(Vec<u8>),
+    Stream(&'a File)
+}
+
+impl<'a> Payload<'a> {
+    fn new(file: &'a File, level: Level) -> RarcResult<Self> {
+        Ok(match compress(file, level)? {
+            Some(data) => Payload::Memory(data),
+            None => Payload::Stream(file)
+        })
+    }
+    fn open(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        match self {
+            Payload::Memory(data) => Ok(Box::new(data.as_slice())),
+            Payload::Stream(file) => file.open_data()
+        }
+    }
+    /// Size and hash of the payload, read in one pass.
+    fn digest(&self) -> std::io::Result<(u64, u64)> {
+        let mut hasher = DefaultHasher::new();
+        let mut reader = self.open()?;
+        let mut buf = [0u8; 0x10000];
+        let mut len = 0u64;
+        loop {
+            let read = reader.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.write(&buf[..read]);
+            len += read as u64;
+        }
+        Ok((len, hasher.finish()))
+    }
+    /// Compares both payloads byte for byte.
+    fn same_as(&self, other: &Self) -> std::io::Result<bool> {
+        let (mut x, mut y) = (self.open()?, other.open()?);
+        let (mut xbuf, mut ybuf) = ([0u8; 0x10000], [0u8; 0x10000]);
+        loop {
+            let read = read_full(&mut x, &mut xbuf)?;
+            if read != read_full(&mut y, &mut ybuf)? || xbuf[..read] != ybuf[..read] {
+                return Ok(false);
+            }
+            if read == 0 {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Reads until `buf` is full or the reader is done.
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match reader.read(&mut buf[total..])? {
+            0 => break,
+            read => total += read
+        }
+    }
+    Ok(total)
+}
+
+/// Lays out one segment's data starting `start` bytes into the file data at
+/// `base`, returning the segment's size. Offsets are stored relative to
+/// `base`, not the segment. Identical data is found by hash and checked byte
+/// for byte before being shared.
+fn layout_file_data(files: Vec<Reference<File>>, base: u64, start: u32, level: Level,
+    data: &mut Vec<Placement>) -> RarcResult<u32> {
+    let mut end = start;
+    // Offset in the segment and index in `data` of everything laid out, by
+    // payload size and hash.
+    let mut dict: HashMap<(u64, u64), Vec<(u32, usize)>> = HashMap::new();
+    for file in files {
+        let (size, offset) = {
+            let lock = file.borrow();
+            let payload = Payload::new(&lock, level)?;
+            let key = payload.digest()?;
+            let mut found = None;
+            for (offset, index) in dict.get(&key).into_iter().flatten() {
+                // Compressed again only when the hashes match.
+                let lock = data[*index].file.borrow();
+                if payload.same_as(&Payload::new(&lock, level)?)? {
+                    found = Some(*offset);
+                    break;
+                }
+            }
+            let offset = match found {
+                Some(offset) => offset,
+                None => {
+                    let offset = end;
+                    end = (end as u64 + key.0).next_multiple_of(32) as u32;
+                    dict.entry(key).or_default().push((offset, data.len()));
+                    data.push(Placement { offset: base + offset as u64, file: file.clone() });
+                    offset
+                }
+            };
+            (key.0 as u32, offset)
+        };
+        let mut file = file.borrow_mut();
+        file.node.data_size = size;
+        file.node.data = offset;
+    }
+    Ok(end - start)
+}
+
+#[cfg(test)]
+mod tests {
+    use binrw::Endian;
+
+    use super::*;
+    use crate::fixture::{read, sample};
+
+    #[test]
+    fn layout_matches_written_bytes() {
+        let archive = sample();
+        let layout = archive.layout().unwrap();
+        let bytes = archive.to_bytes(Endian::Big).unwrap();
+        assert!(!layout.preserved);
+        assert_eq!(bytes.len() as u64, layout.size);
+        assert_eq!(layout.header.size as u64, layout.size);
+        assert_eq!(layout.data.len(), 4);
+        for placement in &layout.data {
+            let file = placement.file.borrow();
+            let off = placement.offset as usize;
+            assert_eq!(off % 32, 0);
+            assert_eq!(bytes[off..off + file.data.len()], file.data);
+        }
+        let read = read(&bytes);
+        let relaid = read.layout().unwrap();
+        assert!(relaid.preserved);
+        assert_eq!(relaid.size, layout.size);
+        assert_eq!(read.header.file_data_off, layout.header.file_data_off);
+        assert_eq!(read.data_header.string_tbl_off, layout.data_header.string_tbl_off);
+    }
+
+    #[test]
+    fn shared_data_is_laid_out_once() {
+        let archive = sample();
+        archive.get_file("stage/d.bin").unwrap().borrow_mut().set_data(b"first file".to_vec());
+        let layout = archive.layout().unwrap();
+        assert_eq!(layout.data.len(), 3);
+        let a = archive.get_file("stage/a.bin").unwrap();
+        let d = archive.get_file("stage/d.bin").unwrap();
+        assert_eq!(a.borrow().node.data, d.borrow().node.data);
+    }
+
+    #[test]
+    fn compressed_files_are_laid_out_compressed() {
+        let archive = sample();
+        let a = archive.get_file("stage/a.bin").unwrap();
+        let d = archive.get_file("stage/d.bin").unwrap();
+        for file in [&a, &d] {
+            file.borrow_mut().set_data(vec![0; 0x400]);
+            file.borrow_mut().attr |= FileAttr::COMPRESSED | FileAttr::USE_SZS;
+        }
+        let layout = archive.layout().unwrap();
+        assert_eq!(layout.data.len(), 3);
+        assert_eq!(a.borrow().node.data, d.borrow().node.data);
+        let bytes = archive.to_bytes(Endian::Big).unwrap();
+        let size = a.borrow().node.data_size as usize;
+        assert!(size < 0x400);
+        let placement = layout.data.iter().find(|x| Reference::ptr_eq(&x.file, &a)).unwrap();
+        let off = placement.offset as usize;
+        assert!(bytes[off..].starts_with(b"Yaz0"));
+        assert_eq!(crate::szs::decompress(&bytes[off..off + size]).unwrap(), vec![0; 0x400]);
+    }
+}
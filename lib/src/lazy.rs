@@ -0,0 +1,291 @@
+use std::io::{Read, SeekFrom, Take};
+use std::ops::Range;
+
+use binrw::{Endian, prelude::*};
+
+use crate::{FileAttr, header::{self, DataHeader, Header}, nodes::{directory, file}};
+use crate::error::{RarcError, RarcResult};
+use crate::table::{NameEncoding, Table};
+
+#[derive(Debug, Clone)]
+/// A directory node as read, see [crate::nodes::Directory].
+pub struct LazyDir {
+    pub node: directory::Node,
+    pub name: String,
+    /// Index of the folder holding this one in [Index::folders], [None] for
+    /// the root.
+    pub parent: Option<usize>
+}
+
+#[derive(Debug, Clone)]
+/// A file node as read, without its data. See [crate::nodes::File].
+pub struct LazyFile {
+    pub node: file::Node,
+    pub attr: FileAttr,
+    pub name: String,
+    /// Index of the folder holding this node in [Index::folders].
+    pub parent: Option<usize>
+}
+
+impl LazyFile {
+    pub const fn is_file(&self) -> bool {
+        self.attr.contains(FileAttr::FILE)
+    }
+    pub const fn is_dir(&self) -> bool {
+        self.attr.contains(FileAttr::FOLDER)
+    }
+    /// Checks if this is a dir and the name is "." or ".."
+    pub fn is_shortcut(&self) -> bool {
+        self.is_dir() && (self.name == "." || self.name == "..")
+    }
+    /// Index of the folder this points to in [Index::folders].
+    pub const fn folder(&self) -> Option<usize> {
+        match self.is_dir() && self.node.data != u32::MAX {
+            true => Some(self.node.data as usize),
+            false => None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Everything in an Archive but the file data: headers, nodes and names.
+/// Cheap to read even for huge Archives, see [ArchiveView] and
+/// [ArchiveReader] to get at the data.
+pub struct Index {
+    pub endian: Endian,
+    pub header: Header,
+    pub data_header: DataHeader,
+    pub folders: Vec<LazyDir>,
+    pub files: Vec<LazyFile>,
+    /// Bytes of the string table holding strings, the rest is padding.
+    pub strings: u32
+}
+
+impl Index {
+    /// Reads the headers, nodes and string table, leaving file data alone.
+    pub fn read<R: BinReaderExt>(reader: &mut R, encoding: NameEncoding) -> RarcResult<Self> {
+        let (endian, header, data_header) = header::read_headers(reader)?;
+        let stream_len = reader.seek(SeekFrom::End(0))?;
+        header::check_node_counts(&header, &data_header, stream_len)?;
+        let base = header.data_header_off as u64;
+        let table = Table::read(reader, base + data_header.string_tbl_off as u64,
+            data_header.string_tbl_size, encoding)?;
+        let strings = table.values().map(|x| table.encoded(x).len() as u32 + 1).sum();
+        reader.seek(SeekFrom::Start(base + data_header.dir_node_off as u64))?;
+        let mut folders = Vec::with_capacity(data_header.dir_node_count as usize);
+        for i in 0..data_header.dir_node_count as usize {
+            let pos = reader.stream_position()?;
+            let node: directory::Node = reader.read_type(endian)
+                .map_err(RarcError::section("directory node", pos))?;
+            let name = table.get(&node.name_off).cloned()
+                .ok_or(RarcError::BadNameOffset { node: i, offset: node.name_off })?;
+            folders.push(LazyDir { node, name, parent: None });
+        }
+        reader.seek(SeekFrom::Start(base + data_header.file_node_off as u64))?;
+        let mut files = Vec::with_capacity(data_header.file_node_count as usize);
+        for i in 0..data_header.file_node_count as usize {
+            let pos = reader.stream_position()?;
+            let node: file::Node = reader.read_type(endian)
+                .map_err(RarcError::section("file node", pos))?;
+            reader.seek(SeekFrom::Current(4))?;
+            let name_off = node.attr_and_off & 0x00FFFFFF;
+            let name = table.get(&name_off).cloned()
+                .ok_or(RarcError::BadNameOffset { node: i, offset: name_off })?;
+            let attr = FileAttr((node.attr_and_off >> 24) as u8);
+            let file = LazyFile { node, attr, name, parent: None };
+            if let Some(folder) = file.folder() && folder >= folders.len() {
+                return Err(RarcError::BadDirectoryIndex { node: i, index: folder as u32 });
+            }
+            files.push(file);
+        }
+        for i in 0..folders.len() {
+            let off = folders[i].node.file_off as usize;
+            let count = folders[i].node.file_count as usize;
+            for index in off..off + count {
+                let file = files.get_mut(index)
+                    .ok_or(RarcError::BadFileIndex { node: i, index: index as u32 })?;
+                file.parent = Some(i);
+                if let Some(folder) = file.folder() && folder != 0 && !file.is_shortcut() {
+                    folders[folder].parent = Some(i);
+                }
+            }
+        }
+        Ok(Self { endian, header, data_header, folders, files, strings })
+    }
+    /// The nodes inside the folder at `folder` in [Index::folders].
+    pub fn children(&self, folder: usize) -> &[LazyFile] {
+        let node = &self.folders[folder].node;
+        let off = node.file_off as usize;
+        &self.files[off..off + node.file_count as usize]
+    }
+    /// In-archive path of the folder at `folder`, separated by `/`.
+    pub fn dir_path(&self, folder: usize) -> String {
+        let mut names = vec![];
+        let mut current = Some(folder);
+        // Bounded so a malformed Archive with a loop can't hang.
+        while let Some(index) = current && names.len() <= self.folders.len() {
+            names.push(self.folders[index].name.as_str());
+            current = self.folders[index].parent;
+        }
+        names.reverse();
+        names.join("/")
+    }
+    /// In-archive path of `file`, separated by `/`.
+    pub fn path(&self, file: &LazyFile) -> String {
+        match file.parent {
+            Some(parent) => format!("{}/{}", self.dir_path(parent), file.name),
+            None => file.name.clone()
+        }
+    }
+    /// Finds the node at an in-archive path such as `stage/jmp/a.bcsv`.
+    pub fn find(&self, path: &str) -> Option<&LazyFile> {
+        let mut parts = path.split('/').filter(|x| !x.is_empty());
+        if parts.next()? != self.folders.first()?.name {
+            return None;
+        }
+        let mut folder = 0;
+        let mut found = None;
+        for part in parts {
+            let child = self.children(folder).iter()
+                .find(|x| !x.is_shortcut() && x.name == part)?;
+            if let Some(next) = child.folder() {
+                folder = next;
+            }
+            found = Some(child);
+        }
+        found
+    }
+    /// Where `file`'s data lives, from the start of the Archive.
+    pub fn data_range(&self, file: &LazyFile) -> Option<Range<u64>> {
+        if !file.is_file() {
+            return None;
+        }
+        let start = (self.header.data_header_off + self.header.file_data_off) as u64
+            + file.node.data as u64;
+        Some(start..start + file.node.data_size as u64)
+    }
+}
+
+/// Checks `file` is a file and gets its data range.
+fn file_range(index: &Index, file: &LazyFile) -> RarcResult<Range<u64>> {
+    index.data_range(file).ok_or_else(|| RarcError::IsADirectory { path: index.path(file) })
+}
+
+/// An Archive read lazily out of a byte buffer, e.g. a decompressed file or a
+/// memory map. File data is borrowed straight from the buffer.
+pub struct ArchiveView<'a> {
+    pub index: Index,
+    data: &'a [u8]
+}
+
+impl<'a> ArchiveView<'a> {
+    pub fn new(data: &'a [u8], encoding: NameEncoding) -> RarcResult<Self> {
+        let index = Index::read(&mut std::io::Cursor::new(data), encoding)?;
+        Ok(Self { index, data })
+    }
+    /// The data of `file`, borrowed from the buffer.
+    pub fn data(&self, file: &LazyFile) -> RarcResult<&'a [u8]> {
+        let range = file_range(&self.index, file)?;
+        self.data.get(range.start as usize..range.end as usize)
+            .ok_or(RarcError::Truncated { section: "file data", offset: range.start })
+    }
+}
+
+/// An Archive read lazily out of a `Read + Seek`, file data is only read when
+/// asked for.
+pub struct ArchiveReader<R> {
+    pub index: Index,
+    reader: R
+}
+
+impl<R: BinReaderExt> ArchiveReader<R> {
+    pub fn new(mut reader: R, encoding: NameEncoding) -> RarcResult<Self> {
+        let index = Index::read(&mut reader, encoding)?;
+        Ok(Self { index, reader })
+    }
+    /// A reader over just the data of `file`.
+    pub fn open(&mut self, file: &LazyFile) -> RarcResult<Take<&mut R>> {
+        let range = file_range(&self.index, file)?;
+        self.reader.seek(SeekFrom::Start(range.start))?;
+        Ok((&mut self.reader).take(range.end - range.start))
+    }
+    /// Reads the whole data of `file`.
+    pub fn read_data(&mut self, file: &LazyFile) -> RarcResult<Vec<u8>> {
+        let range = file_range(&self.index, file)?;
+        let mut result = vec![0u8; (range.end - range.start) as usize];
+        self.reader.seek(SeekFrom::Start(range.start))?;
+        self.reader.read_exact(&mut result).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof =>
+                RarcError::Truncated { section: "file data", offset: range.start },
+            _ => e.into()
+        })?;
+        Ok(result)
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fixture::{put_u32, read, sample_bytes};
+
+    #[test]
+    fn agrees_with_eager_read() {
+        let bytes = sample_bytes();
+        let eager = read(&bytes);
+        let view = ArchiveView::new(&bytes, NameEncoding::Utf8).unwrap();
+        let mut reader = ArchiveReader::new(Cursor::new(&bytes), NameEncoding::Utf8).unwrap();
+        let index = &view.index;
+        assert_eq!(index.folders.len(), eager.folders.len());
+        assert_eq!(index.files.len(), eager.files.len());
+        for (lazy, file) in index.files.iter().zip(&eager.files) {
+            let file = file.borrow();
+            assert_eq!((&lazy.name, lazy.attr, lazy.node.id), (&file.name, file.attr, file.node.id));
+        }
+        let mut paths = vec![];
+        for file in index.files.iter().filter(|x| x.is_file()) {
+            let path = index.path(file);
+            let data = eager.get_file(&path).unwrap().borrow().data.clone();
+            assert_eq!(index.find(&path).map(|x| &x.name), Some(&file.name));
+            let range = index.data_range(file).unwrap();
+            assert_eq!(&bytes[range.start as usize..range.end as usize], data.as_slice());
+            assert_eq!(view.data(file).unwrap(), data.as_slice());
+            assert_eq!(reader.read_data(file).unwrap(), data);
+            paths.push(path);
+        }
+        assert_eq!(paths, ["stage/a.bin", "stage/d.bin", "stage/jmp/b.bcsv", "stage/jmp/Model/c.bin"]);
+        let jmp = index.find("stage/jmp").unwrap();
+        assert!(matches!(view.data(jmp), Err(RarcError::IsADirectory { .. })));
+        assert!(index.find("stage/nope").is_none());
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let bytes = sample_bytes();
+        assert!(matches!(ArchiveView::new(&bytes[..0x30], NameEncoding::Utf8), Err(RarcError::Truncated { .. })));
+        assert!(matches!(ArchiveView::new(&bytes[..0x50], NameEncoding::Utf8), Err(RarcError::Truncated { .. })));
+        // The index fits, the data of the last file doesn't.
+        let view = ArchiveView::new(&bytes, NameEncoding::Utf8).unwrap();
+        let last = view.index.files.iter().filter_map(|x| view.index.data_range(x)).max_by_key(|x| x.end).unwrap();
+        let cut = &bytes[..last.start as usize + 1];
+        let view = ArchiveView::new(cut, NameEncoding::Utf8).unwrap();
+        let mut reader = ArchiveReader::new(Cursor::new(cut), NameEncoding::Utf8).unwrap();
+        let file = view.index.files.iter().find(|x| view.index.data_range(x) == Some(last.clone())).unwrap();
+        assert!(matches!(view.data(file), Err(RarcError::Truncated { .. })));
+        assert!(matches!(reader.read_data(file), Err(RarcError::Truncated { .. })));
+    }
+
+    #[test]
+    fn forged_node_counts_are_errors() {
+        for (off, section) in [(0x20, "directory nodes"), (0x28, "file nodes")] {
+            let mut bytes = sample_bytes();
+            put_u32(&mut bytes, off, 0x0FFF_FFFF);
+            assert!(matches!(ArchiveView::new(&bytes, NameEncoding::Utf8),
+                Err(RarcError::Truncated { section: s, .. }) if s == section));
+        }
+    }
+}
@@ -1,13 +1,29 @@
-use std::{rc::Rc, cell::RefCell};
-use std::io::Cursor;
-
-use yaz0::*;
-
 pub mod header;
+mod reference;
 pub mod nodes;
 pub mod archive;
 pub mod table;
 pub mod iter;
+pub mod lookup;
+pub mod collision;
+pub mod edit;
+pub mod manifest;
+pub mod rules;
+pub mod preserve;
+pub mod compression;
+pub mod yay0;
+pub mod szs;
+pub mod format;
+pub mod layout;
+pub mod lazy;
+pub mod info;
+pub mod u8;
+pub mod sarc;
+pub mod verify;
+mod lz;
+pub mod error;
+#[cfg(test)]
+mod fixture;
 pub use binrw;
 pub use yaz0;
 
@@ -17,36 +33,46 @@ pub mod c_exports;
 #[cfg(feature = "cxx")]
 pub mod cpp_exports;
 
-/// Typedef for the Node types to make use of.
-pub type Reference<T> = Rc<RefCell<T>>;
-
 pub use archive::Archive;
+pub use reference::{Reference, WeakReference};
 pub use nodes::file::FileAttr;
+pub use error::{RarcError, RarcResult};
+pub use lookup::Entry;
+pub use manifest::Manifest;
+pub use format::Format;
 
 /// Utility method to easily make a [Reference].
 pub fn make_reference<T>(item: T) -> Reference<T> {
-    Rc::new(RefCell::new(item))
+    Reference::new(item)
 }
 
+/// Decompresses `buf` if it's a Yaz0 stream, otherwise returns it unchanged.
+/// Use [compression::decompress_payload] to get an error for a corrupt stream.
 pub fn decompres_yaz0<A: AsRef<[u8]>>(buf: A) -> Vec<u8> {
-    let buf = Vec::from(buf.as_ref());
-    let reader = Cursor::new(buf.clone());
-    if let Ok(mut archive) = 
-        Yaz0Archive::new(reader) &&
-        let Ok(buffer) = archive.decompress() {
-            buffer
-        }
-    else {
-        buf
-    }
+    szs::decompress(buf.as_ref()).unwrap_or_else(|_| Vec::from(buf.as_ref()))
+}
+
+/// Decompresses `buf` if it's a Yay0 stream, otherwise returns it unchanged.
+pub fn decompress_yay0<A: AsRef<[u8]>>(buf: A) -> Vec<u8> {
+    yay0::decompress(buf.as_ref()).unwrap_or_else(|_| Vec::from(buf.as_ref()))
 }
 
-pub fn compress_yaz0<A: AsRef<[u8]>>(buf: A, level: CompressionLevel) -> Vec<u8> {
-    let mut writer = Cursor::new(vec![]);
-    let yaz0 = Yaz0Writer::new(&mut writer);
-    if let Ok(_) = yaz0.compress_and_write(buf.as_ref(), level) {
-        writer.into_inner()
-    } else {
-        Vec::from(buf.as_ref())
+/// Decompresses `buf` as Yaz0 or Yay0 depending on its magic, anything else
+/// is returned unchanged.
+pub fn decompress<A: AsRef<[u8]>>(buf: A) -> Vec<u8> {
+    let buf = buf.as_ref();
+    match compression::decompress_payload(buf) {
+        Ok(data) => data.into_owned(),
+        Err(_) => Vec::from(buf)
     }
+}
+
+pub fn compress_yay0<A: AsRef<[u8]>>(buf: A, level: compression::Level) -> Vec<u8> {
+    yay0::compress(buf.as_ref(), level)
+}
+
+/// Compresses `buf` into a Yaz0 stream, `level` may be a [yaz0::CompressionLevel]
+/// or a [compression::Level] such as [compression::Level::Nintendo].
+pub fn compress_yaz0<A: AsRef<[u8]>, L: Into<compression::Level>>(buf: A, level: L) -> Vec<u8> {
+    szs::compress(buf.as_ref(), level.into())
 }
\ No newline at end of file
@@ -0,0 +1,110 @@
+use crate::{Archive, Reference, nodes::*};
+use crate::error::{RarcError, RarcResult};
+
+/// What a path inside an Archive resolved to.
+#[derive(Debug, Clone)]
+pub enum Entry {
+    File(Reference<File>),
+    Dir(Reference<Directory>)
+}
+
+impl Archive {
+    /// Resolves a full `/` separated path starting at the root's name,
+    /// e.g. `stage/jmp/Placement/Common/ObjInfo`. "." and ".." are followed
+    /// through the folders' own shortcut nodes, so going above the root fails.
+    pub fn lookup<A: AsRef<str>>(&self, path: A) -> RarcResult<Entry> {
+        let path = path.as_ref();
+        let mut parts = path.split('/').filter(|x| !x.is_empty());
+        let mut walked = String::new();
+        let not_found = |walked: &str| RarcError::NotFound { path: walked.into() };
+        match parts.next() {
+            Some(name) if name == self.root.borrow().name || name == "." => walked.push_str(name),
+            Some(name) => return Err(not_found(name)),
+            None => return Err(not_found(path))
+        }
+        let mut current = self.root.clone();
+        while let Some(part) = parts.next() {
+            walked.push('/');
+            walked.push_str(part);
+            let child = current.borrow().children.iter()
+                .find(|x| x.borrow().name == part)
+                .cloned()
+                .ok_or_else(|| not_found(&walked))?;
+            let (is_dir, folder) = {
+                let child = child.borrow();
+                (child.is_dir(), child.get_folder())
+            };
+            if is_dir {
+                current = folder.ok_or_else(|| not_found(&walked))?;
+            } else if parts.clone().next().is_some() {
+                return Err(RarcError::NotADirectory { path: walked });
+            } else {
+                return Ok(Entry::File(child));
+            }
+        }
+        Ok(Entry::Dir(current))
+    }
+    /// Resolves `path` to a file, see [Archive::lookup].
+    pub fn get_file<A: AsRef<str>>(&self, path: A) -> RarcResult<Reference<File>> {
+        match self.lookup(path.as_ref())? {
+            Entry::File(file) => Ok(file),
+            Entry::Dir(_) => Err(RarcError::IsADirectory { path: path.as_ref().into() })
+        }
+    }
+    /// Resolves `path` to a directory, see [Archive::lookup].
+    pub fn get_dir<A: AsRef<str>>(&self, path: A) -> RarcResult<Reference<Directory>> {
+        match self.lookup(path.as_ref())? {
+            Entry::Dir(dir) => Ok(dir),
+            Entry::File(_) => Err(RarcError::NotADirectory { path: path.as_ref().into() })
+        }
+    }
+    /// Checks if `path` resolves to either a file or a directory.
+    pub fn exists<A: AsRef<str>>(&self, path: A) -> bool {
+        self.lookup(path).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::sample;
+
+    fn dir_name(entry: RarcResult<Entry>) -> String {
+        match entry.unwrap() {
+            Entry::Dir(dir) => dir.borrow().name.clone(),
+            Entry::File(file) => panic!("{} isn't a dir", file.borrow().name)
+        }
+    }
+
+    #[test]
+    fn shortcuts_are_followed() {
+        let archive = sample();
+        assert_eq!(dir_name(archive.lookup("stage/jmp/.")), "jmp");
+        assert_eq!(dir_name(archive.lookup("stage/jmp/Model/..")), "jmp");
+        assert_eq!(dir_name(archive.lookup("./jmp/../jmp/Model")), "Model");
+        assert_eq!(archive.get_file("stage/jmp/../a.bin").unwrap().borrow().name, "a.bin");
+        assert!(matches!(archive.lookup("stage/.."), Err(RarcError::NotFound { .. })));
+    }
+
+    #[test]
+    fn empty_parts_are_skipped() {
+        let archive = sample();
+        assert_eq!(dir_name(archive.lookup("stage/jmp/")), "jmp");
+        assert_eq!(dir_name(archive.lookup("/stage//jmp")), "jmp");
+        assert!(archive.get_file("stage/a.bin/").is_ok());
+    }
+
+    #[test]
+    fn missing_and_mismatched_paths() {
+        let archive = sample();
+        assert!(matches!(archive.lookup("stage/A.BIN"), Err(RarcError::NotFound { path }) if path == "stage/A.BIN"));
+        assert!(matches!(archive.lookup("Stage/a.bin"), Err(RarcError::NotFound { .. })));
+        assert!(matches!(archive.lookup("stage/jmp/x/y"), Err(RarcError::NotFound { path }) if path == "stage/jmp/x"));
+        assert!(matches!(archive.lookup(""), Err(RarcError::NotFound { .. })));
+        assert!(matches!(archive.get_file("stage/jmp"), Err(RarcError::IsADirectory { .. })));
+        assert!(matches!(archive.get_dir("stage/a.bin"), Err(RarcError::NotADirectory { .. })));
+        assert!(matches!(archive.lookup("stage/a.bin/x"), Err(RarcError::NotADirectory { path }) if path == "stage/a.bin"));
+        assert!(archive.exists("stage/jmp/Model/c.bin"));
+        assert!(!archive.exists("stage/jmp/Model/C.bin"));
+    }
+}
@@ -0,0 +1,475 @@
+use crate::compression::Level;
+
+/// How far back a match may reach, shared by Yaz0 and Yay0.
+pub(crate) const WINDOW: usize = 0x1000;
+/// Shortest match worth encoding.
+pub(crate) const MIN_MATCH: usize = 3;
+/// Longest match a single token can hold, 0xFF + 0x12.
+pub(crate) const MAX_MATCH: usize = 0x111;
+
+/// Most the output of a decompressor is reserved up front per input byte.
+/// Real streams rarely shrink data further than this, and a forged header
+/// size shouldn't make us allocate gigabytes before the first byte's read.
+const RESERVE_RATIO: usize = 16;
+
+const HASH_BITS: u32 = 15;
+const NONE: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// One step of an LZ stream, shared by every Nintendo LZ container.
+pub(crate) enum Token {
+    Literal(u8),
+    /// Copy `len` bytes starting `dist` bytes back.
+    Match { dist: u16, len: u16 }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// How hard [tokenize_parallel] searches.
+pub(crate) struct Params {
+    /// Most candidates checked per position.
+    pub max_chain: usize,
+    /// Check if starting a match one byte later gives a longer one.
+    pub lazy: bool
+}
+
+impl From<Level> for Params {
+    fn from(value: Level) -> Self {
+        let lazy = matches!(value, Level::Lookahead { .. });
+        Self { max_chain: value.search_depth(), lazy }
+    }
+}
+
+/// What to reserve for `size` decompressed bytes out of `input` bytes of
+/// compressed stream, see [RESERVE_RATIO]. Bigger outputs just grow.
+pub(crate) fn reserve(size: usize, input: usize) -> usize {
+    size.min(input.saturating_mul(RESERVE_RATIO))
+}
+
+/// Hash-chain match finder over a sliding [WINDOW].
+pub(crate) struct MatchFinder<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    max_chain: usize,
+    /// Next position that still has to be inserted.
+    next: usize
+}
+
+impl<'a> MatchFinder<'a> {
+    /// Makes a finder for matches starting at or after `start`, which can
+    /// still reach back into the [WINDOW] before it.
+    pub fn new(data: &'a [u8], start: usize, max_chain: usize) -> Self {
+        Self {
+            data,
+            head: vec![NONE; 1 << HASH_BITS],
+            prev: vec![NONE; WINDOW],
+            max_chain: max_chain.max(1),
+            next: start.saturating_sub(WINDOW)
+        }
+    }
+    fn hash(&self, pos: usize) -> usize {
+        let d = self.data;
+        let key = (d[pos] as u32) << 16 | (d[pos + 1] as u32) << 8 | d[pos + 2] as u32;
+        (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+    /// Inserts every position before `pos` into the chains.
+    fn advance(&mut self, pos: usize) {
+        while self.next < pos {
+            let at = self.next;
+            if at + MIN_MATCH <= self.data.len() {
+                let hash = self.hash(at);
+                self.prev[at % WINDOW] = self.head[hash];
+                self.head[hash] = at;
+            }
+            self.next += 1;
+        }
+    }
+    /// Every earlier position within the [WINDOW] that shares a hash with
+    /// `pos`, nearest first.
+    fn candidates(&mut self, pos: usize) -> impl Iterator<Item = usize> + '_ {
+        self.advance(pos);
+        let mut cand = match pos + MIN_MATCH <= self.data.len() {
+            true => self.head[self.hash(pos)],
+            false => NONE
+        };
+        std::iter::from_fn(move || {
+            if cand == NONE || cand >= pos || pos - cand > WINDOW {
+                return None;
+            }
+            let found = cand;
+            let next = self.prev[cand % WINDOW];
+            cand = if next >= cand { NONE } else { next };
+            Some(found)
+        })
+    }
+    /// Longest match for `pos` ending before `end` as `(dist, len)`, nearest
+    /// wins on ties.
+    pub fn find(&mut self, pos: usize, end: usize) -> (usize, usize) {
+        let data = self.data;
+        let max = usize::min(MAX_MATCH, end - pos);
+        if max < MIN_MATCH {
+            return (0, 0);
+        }
+        let max_chain = self.max_chain;
+        let mut best = (0, 0);
+        for cand in self.candidates(pos).take(max_chain) {
+            if data[cand + best.1.min(max - 1)] == data[pos + best.1.min(max - 1)] {
+                let len = data[cand..cand + max].iter()
+                    .zip(&data[pos..pos + max])
+                    .take_while(|(x, y)| x == y)
+                    .count();
+                if len > best.1 {
+                    best = (pos - cand, len);
+                    if len == max {
+                        break;
+                    }
+                }
+            }
+        }
+        match best.1 >= MIN_MATCH {
+            true => best,
+            false => (0, 0)
+        }
+    }
+}
+
+/// Match search that picks exactly what Nintendo's encoder picks: every
+/// position in the [WINDOW] is checked, matches aren't capped at [MAX_MATCH]
+/// while comparing and the farthest one wins on ties.
+struct NintendoFinder<'a> {
+    finder: MatchFinder<'a>,
+    /// Per distance, a `(start, end)` range known to match that far back,
+    /// `end` being the first mismatch. Keeps long runs from being rescanned.
+    runs: Vec<(usize, usize)>,
+    /// The `start..end` run of a single repeated byte around the last
+    /// position searched, every match inside it goes to its end.
+    fill: (usize, usize)
+}
+
+impl<'a> NintendoFinder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { finder: MatchFinder::new(data, 0, usize::MAX), runs: vec![(1, 0); WINDOW + 1], fill: (0, 0) }
+    }
+    fn update_fill(&mut self, pos: usize) {
+        let (start, end) = self.fill;
+        if start <= pos && pos < end {
+            return;
+        }
+        let data = self.finder.data;
+        let byte = data[pos];
+        let start = pos - data[..pos].iter().rev().take_while(|x| **x == byte).count();
+        let end = pos + data[pos..].iter().take_while(|x| **x == byte).count();
+        self.fill = (start, end);
+    }
+    /// Length of the match between `pos` and `pos - dist`, up to the end of data.
+    fn match_len(&mut self, pos: usize, dist: usize) -> usize {
+        if pos - dist >= self.fill.0 {
+            return self.fill.1 - pos;
+        }
+        let (start, end) = self.runs[dist];
+        if start <= pos && pos <= end {
+            return end - pos;
+        }
+        let data = self.finder.data;
+        let end = pos + data[pos..].iter()
+            .zip(&data[pos - dist..])
+            .take_while(|(x, y)| x == y)
+            .count();
+        self.runs[dist] = (pos, end);
+        end - pos
+    }
+    /// Ditto of [MatchFinder::find], but uncapped and farthest wins on ties.
+    fn find(&mut self, pos: usize) -> (usize, usize) {
+        let cands: Vec<_> = self.finder.candidates(pos).collect();
+        if !cands.is_empty() {
+            self.update_fill(pos);
+        }
+        let mut best = (0, 0);
+        for cand in cands {
+            let len = self.match_len(pos, pos - cand);
+            if len >= best.1 {
+                best = (pos - cand, len);
+            }
+        }
+        match best.1 >= MIN_MATCH {
+            true => best,
+            false => (0, 0)
+        }
+    }
+}
+
+/// Splits `data` into the same tokens Nintendo's Yaz0 and Yay0 encoders emit.
+/// A match is only put off by a byte if the next position's is at least 2
+/// longer, and the next position then takes its match as is.
+fn tokenize_nintendo(data: &[u8], mut emit: impl FnMut(Token)) {
+    let mut finder = NintendoFinder::new(data);
+    let mut pos = 0;
+    let mut pending = None;
+    while pos < data.len() {
+        let (dist, len) = match pending.take() {
+            Some(found) => found,
+            None => {
+                let found = finder.find(pos);
+                let next = match found.1 >= MIN_MATCH {
+                    true => finder.find(pos + 1),
+                    false => (0, 0)
+                };
+                if next.1 >= found.1 + 2 {
+                    pending = Some(next);
+                    (0, 0)
+                } else {
+                    found
+                }
+            }
+        };
+        if len < MIN_MATCH {
+            emit(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        }
+        let len = len.min(MAX_MATCH);
+        emit(Token::Match { dist: dist as u16, len: len as u16 });
+        pos += len;
+    }
+}
+
+/// Splits `data[start..end]` into literals and back references.
+fn tokenize_range(data: &[u8], start: usize, end: usize, params: Params, mut emit: impl FnMut(Token)) {
+    let mut finder = MatchFinder::new(data, start, params.max_chain);
+    let mut pos = start;
+    let mut pending = None;
+    while pos < end {
+        let (dist, len) = match pending.take() {
+            Some(found) => found,
+            None => finder.find(pos, end)
+        };
+        if len < MIN_MATCH {
+            emit(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        }
+        if params.lazy && len < MAX_MATCH && pos + 1 < end {
+            let next = finder.find(pos + 1, end);
+            if next.1 > len {
+                emit(Token::Literal(data[pos]));
+                pos += 1;
+                pending = Some(next);
+                continue;
+            }
+        }
+        emit(Token::Match { dist: dist as u16, len: len as u16 });
+        pos += len;
+    }
+}
+
+/// Size of the pieces [tokenize_parallel] hands to each thread. Matches never
+/// cross a piece's end, but can still reach back into the previous one.
+const CHUNK: usize = 0x40000;
+
+/// Splits `data` into literals and back references, searching up to `threads`
+/// pieces of it at once. Pieces are the same for any thread count, so the
+/// tokens are too.
+fn tokenize_parallel(data: &[u8], params: Params, threads: usize, mut emit: impl FnMut(Token)) {
+    let ranges: Vec<_> = (0..data.len()).step_by(CHUNK)
+        .map(|x| (x, usize::min(x + CHUNK, data.len())))
+        .collect();
+    if threads <= 1 || ranges.len() <= 1 {
+        for (start, end) in ranges {
+            tokenize_range(data, start, end, params, &mut emit);
+        }
+        return;
+    }
+    for batch in ranges.chunks(threads) {
+        let results: Vec<Vec<Token>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = batch.iter()
+                .map(|&(start, end)| scope.spawn(move || {
+                    let mut tokens = Vec::with_capacity((end - start) / 2);
+                    tokenize_range(data, start, end, params, |x| tokens.push(x));
+                    tokens
+                }))
+                .collect();
+            handles.into_iter()
+                .map(|x| x.join().expect("match finder thread panicked"))
+                .collect()
+        });
+        for token in results.into_iter().flatten() {
+            emit(token);
+        }
+    }
+}
+
+/// Splits `data` into literals and back references as `level` asks for.
+/// [Level::Nintendo] always searches on a single thread.
+pub(crate) fn tokenize(data: &[u8], level: Level, threads: usize, emit: impl FnMut(Token)) {
+    match level {
+        Level::Nintendo => tokenize_nintendo(data, emit),
+        _ => tokenize_parallel(data, level.into(), threads, emit)
+    }
+}
+
+#[cfg(test)]
+/// Checks every LZ codec has to pass, each codec's tests run them on its own
+/// [checks::Codec].
+pub(crate) mod checks {
+    use crate::compression::Level;
+    use crate::error::RarcResult;
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct Codec {
+        pub compress: fn(&[u8], Level, usize) -> Vec<u8>,
+        pub decompress: fn(&[u8]) -> RarcResult<Vec<u8>>
+    }
+
+    /// Deterministic noise over `alphabet` letters, the fewer the more matches.
+    pub(crate) fn noise(len: usize, alphabet: u8, mut seed: u32) -> Vec<u8> {
+        (0..len).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            b'a' + (seed >> 16) as u8 % alphabet
+        }).collect()
+    }
+
+    /// Runs, repeats from far back and plain noise, past one [super::WINDOW].
+    pub(crate) fn sample() -> Vec<u8> {
+        let mut data = noise(0x1800, 4, 1);
+        data.extend(vec![0u8; 0x400]);
+        data.extend(noise(0x800, 26, 2));
+        data.extend_from_within(0x100..0x900);
+        data
+    }
+
+    fn levels() -> impl Iterator<Item = Level> {
+        (1..=10).flat_map(|quality| [Level::Naive { quality }, Level::Lookahead { quality }])
+            .chain([Level::Nintendo])
+    }
+
+    pub(crate) fn round_trip_every_level(codec: Codec) {
+        for data in [vec![], vec![7], sample()] {
+            for level in levels() {
+                let compressed = (codec.compress)(&data, level, 1);
+                assert_eq!((codec.decompress)(&compressed).unwrap(), data, "{:?}", level);
+            }
+        }
+    }
+
+    pub(crate) fn same_output_for_any_thread_count(codec: Codec) {
+        let data = sample();
+        for level in levels() {
+            let single = (codec.compress)(&data, level, 1);
+            for threads in [2, 3, 8] {
+                assert!((codec.compress)(&data, level, threads) == single, "{:?} on {} threads", level, threads);
+            }
+        }
+    }
+
+    pub(crate) fn truncated_streams_fail(codec: Codec) {
+        let compressed = (codec.compress)(&sample(), Level::default(), 1);
+        for len in [0, 3, 8, 0x10, compressed.len() / 2, compressed.len() - 1] {
+            assert!((codec.decompress)(&compressed[..len]).is_err(), "cut at {:#x}", len);
+        }
+        // A forged size runs out of stream instead of allocating it all.
+        let mut forged = compressed;
+        forged[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!((codec.decompress)(&forged).is_err());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::checks::noise;
+
+    /// Straight port of the widely used `simpleEnc`, the exhaustive search
+    /// Nintendo's encoder is known to run. Returns `(dist, len)`, len 1 for
+    /// a literal.
+    fn simple_enc(data: &[u8], pos: usize) -> (usize, usize) {
+        let mut best = (0, 1);
+        for i in pos.saturating_sub(WINDOW)..pos {
+            let len = data[pos..].iter().zip(&data[i..]).take_while(|(x, y)| x == y).count();
+            if len > best.1 {
+                best = (pos - i, len);
+            }
+        }
+        match best.1 {
+            2 => (0, 1),
+            _ => best
+        }
+    }
+
+    /// Port of `nintendoEnc` and the loop driving it, the reference
+    /// [tokenize_nintendo] has to agree with.
+    fn reference(data: &[u8]) -> Vec<Token> {
+        let mut result = vec![];
+        let (mut pos, mut pending) = (0, None);
+        while pos < data.len() {
+            let (dist, len) = match pending.take() {
+                Some(found) => found,
+                None => {
+                    let found = simple_enc(data, pos);
+                    let next = simple_enc(data, pos + 1);
+                    match found.1 >= 3 && next.1 >= found.1 + 2 {
+                        true => {
+                            pending = Some(next);
+                            (0, 1)
+                        },
+                        false => found
+                    }
+                }
+            };
+            if len < MIN_MATCH {
+                result.push(Token::Literal(data[pos]));
+                pos += 1;
+            } else {
+                let len = len.min(MAX_MATCH);
+                result.push(Token::Match { dist: dist as u16, len: len as u16 });
+                pos += len;
+            }
+        }
+        result
+    }
+
+    fn nintendo(data: &[u8]) -> Vec<Token> {
+        let mut result = vec![];
+        tokenize(data, Level::Nintendo, 1, |x| result.push(x));
+        result
+    }
+
+    #[test]
+    fn reserve_is_capped_by_the_input() {
+        assert_eq!(reserve(0x100, 0x20), 0x100);
+        assert_eq!(reserve(u32::MAX as usize, 0x20), 0x20 * RESERVE_RATIO);
+        assert_eq!(reserve(usize::MAX, usize::MAX), usize::MAX);
+    }
+
+    #[test]
+    fn nintendo_known_vectors() {
+        assert_eq!(nintendo(b""), vec![]);
+        assert_eq!(nintendo(b"aaaaaaaaaa"), vec![Token::Literal(b'a'), Token::Match { dist: 1, len: 9 }]);
+        assert_eq!(nintendo(b"abcabcabc"), vec![
+            Token::Literal(b'a'), Token::Literal(b'b'), Token::Literal(b'c'),
+            Token::Match { dist: 3, len: 6 }
+        ]);
+        // "abc" matches 3 at 10, but "bcdef" at 11 matches 5: the literal
+        // goes first and the longer match follows as is.
+        let mut tokens: Vec<_> = b"abcXbcdefYa".iter().map(|x| Token::Literal(*x)).collect();
+        tokens.push(Token::Match { dist: 7, len: 5 });
+        assert_eq!(nintendo(b"abcXbcdefYabcdef"), tokens);
+    }
+
+    #[test]
+    fn nintendo_matches_reference() {
+        let mut inputs = vec![
+            vec![0u8; 0x300],
+            b"xyzxyzxbcdxyzxbcdexyzzzzzz".repeat(20),
+            noise(0x2000, 2, 1),
+            noise(0x1800, 4, 2),
+            noise(0x1000, 26, 3)
+        ];
+        let mut mixed = noise(0x600, 3, 4);
+        mixed.extend(vec![b'q'; 0x250]);
+        mixed.extend(noise(0x600, 3, 4));
+        inputs.push(mixed);
+        for data in inputs {
+            assert_eq!(nintendo(&data), reference(&data));
+        }
+    }
+}
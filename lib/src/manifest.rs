@@ -0,0 +1,234 @@
+use std::{collections::HashMap, ffi::OsString, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Archive, FileAttr, Reference, nodes::*, iter};
+use crate::table::NameEncoding;
+use crate::archive::UnpackOptions;
+use crate::error::RarcResult;
+
+/// Extension appended to an unpacked root dir to get its manifest.
+pub const MANIFEST_EXTENSION: &str = ".rarc.json";
+
+impl Serialize for FileAttr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        bitflags::serde::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FileAttr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        bitflags::serde::deserialize(deserializer)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// A single file or folder node, addressed by its path below the root.
+pub struct ManifestNode {
+    pub path: String,
+    pub attr: FileAttr,
+    pub id: u16
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Everything [Directory::unpack] can't store on disk: the [crate::header::DataHeader]
+/// flags, each node's attributes and id, and the order of folders and nodes.
+pub struct Manifest {
+    pub sync: bool,
+    pub next_idx: u16,
+    #[serde(default)]
+    /// How names were stored in the string table.
+    pub encoding: NameEncoding,
+    /// Every folder in [Archive::folders] order, the root is "".
+    pub folders: Vec<String>,
+    /// Every non shortcut node in [Archive::files] order.
+    pub nodes: Vec<ManifestNode>
+}
+
+/// Path of a File below the root, separated by `/`.
+fn relative_path(file: &File) -> String {
+    let path = iter::archive_path(file);
+    match path.split_once('/') {
+        Some((_, rest)) => rest.into(),
+        None => String::new()
+    }
+}
+
+/// Path of a Directory below the root, the root itself is "".
+fn relative_dir(dir: &Directory) -> String {
+    match dir.get_file() {
+        Some(file) => relative_path(&file.borrow()),
+        None => String::new()
+    }
+}
+
+impl Manifest {
+    /// Where the manifest for a root dir at `dir` lives, e.g. `stage.rarc.json`.
+    pub fn sidecar<A: AsRef<Path>>(dir: A) -> PathBuf {
+        let mut path = OsString::from(dir.as_ref().as_os_str());
+        path.push(MANIFEST_EXTENSION);
+        path.into()
+    }
+    pub fn read<A: AsRef<Path>>(path: A) -> RarcResult<Self> {
+        let data = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+    pub fn write<A: AsRef<Path>>(&self, path: A) -> RarcResult<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+impl Archive {
+    /// Records everything needed to rebuild this Archive's attributes, ids
+    /// and ordering from an unpacked directory.
+    pub fn manifest(&self) -> Manifest {
+        let folders = self.folders.iter()
+            .map(|x| relative_dir(&x.borrow()))
+            .collect();
+        let nodes = self.files.iter()
+            .map(|x| x.borrow())
+            .filter(|x| !x.is_shortcut())
+            .map(|x| ManifestNode { path: relative_path(&x), attr: x.attr, id: x.node.id })
+            .collect();
+        Manifest { sync: self.sync(), next_idx: self.next_id(), encoding: self.encoding, folders, nodes }
+    }
+    /// Same as [Archive::unpack], but also writes the [Manifest] next to the
+    /// unpacked root dir.
+    pub fn unpack_with_manifest<A: AsRef<Path>>(&self, dir: A) -> RarcResult<PathBuf> {
+        self.unpack_with(dir, UnpackOptions { manifest: true, ..Default::default() })
+    }
+    /// Reorders, re-attributes and re-numbers freshly imported nodes to match
+    /// `manifest`. Nodes the manifest doesn't know keep their import order
+    /// after the known ones, and ids are only restored if the node set matches.
+    /// Leaves [Archive::check_collisions] to the caller.
+    pub(crate) fn apply_manifest(&mut self, manifest: &Manifest) -> RarcResult<()> {
+        let node_order: HashMap<_, _> = manifest.nodes.iter()
+            .enumerate()
+            .map(|(i, x)| (x.path.as_str(), (i, x)))
+            .collect();
+        let folder_order: HashMap<_, _> = manifest.folders.iter()
+            .enumerate()
+            .map(|(i, x)| (x.as_str(), i))
+            .collect();
+        *self.sync_mut() = manifest.sync;
+        self.encoding = manifest.encoding;
+        for folder in &self.folders {
+            let mut children = folder.borrow().children.clone();
+            children.sort_by_cached_key(|x| {
+                let path = relative_path(&x.borrow());
+                node_order.get(path.as_str()).map_or(usize::MAX, |x| x.0)
+            });
+            for child in &children {
+                let path = relative_path(&child.borrow());
+                let mut child = child.borrow_mut();
+                if !child.is_shortcut()
+                    && let Some((_, node)) = node_order.get(path.as_str())
+                    && node.attr.contains(FileAttr::FILE) == child.is_file() {
+                    child.attr = node.attr;
+                }
+            }
+            folder.borrow_mut().children = children;
+        }
+        let root = self.root.clone();
+        self.folders.sort_by_cached_key(|x| {
+            if Reference::ptr_eq(x, &root) {
+                return 0;
+            }
+            let path = relative_dir(&x.borrow());
+            folder_order.get(path.as_str()).map_or(usize::MAX, |x| *x)
+        });
+        self.sort_unchecked()?;
+        let paths: Vec<_> = self.files.iter()
+            .map(|x| x.borrow())
+            .filter(|x| !x.is_shortcut())
+            .map(|x| relative_path(&x))
+            .collect();
+        let same = paths.len() == manifest.nodes.len()
+            && paths.iter().all(|x| node_order.contains_key(x.as_str()));
+        if same {
+            for file in &self.files {
+                let path = relative_path(&file.borrow());
+                if let Some((_, node)) = node_order.get(path.as_str()) {
+                    file.borrow_mut().node.id = node.id;
+                }
+            }
+            *self.next_id_mut() = manifest.next_idx;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use binrw::Endian;
+
+    use super::*;
+    use crate::fixture::{sample, temp_dir};
+
+    /// [sample] with ids, attributes and flags no fresh import would give.
+    fn tweaked() -> Archive {
+        let mut archive = sample();
+        *archive.sync_mut() = false;
+        archive.get_file("stage/jmp/b.bcsv").unwrap().borrow_mut().attr = FileAttr::FILE | FileAttr::LOAD_FROM_DVD;
+        for (i, file) in archive.files.iter().filter(|x| x.borrow().is_file()).enumerate() {
+            file.borrow_mut().node.id = 10 + i as u16 * 3;
+        }
+        *archive.next_id_mut() = 40;
+        archive.encoding = NameEncoding::ShiftJis;
+        archive
+    }
+
+    fn import(dir: &Path) -> Archive {
+        let mut archive = Archive::create("stage", true);
+        archive.import(dir, FileAttr::FILE | FileAttr::LOAD_TO_MRAM).unwrap();
+        archive
+    }
+
+    #[test]
+    fn unpack_and_import_round_trip() {
+        let archive = tweaked();
+        let dir = temp_dir("manifest_round_trip");
+        let root = archive.unpack_with_manifest(&dir).unwrap();
+        assert!(Manifest::sidecar(&root).is_file());
+        let imported = import(&root);
+        assert_eq!(imported.manifest(), archive.manifest());
+        assert!(!imported.sync());
+        assert_eq!(imported.next_id(), 40);
+        assert_eq!(imported.encoding, NameEncoding::ShiftJis);
+        assert_eq!(imported.to_bytes(Endian::Big).unwrap(), archive.to_bytes(Endian::Big).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_and_missing_nodes() {
+        let archive = tweaked();
+        let dir = temp_dir("manifest_missing");
+        let root = archive.unpack_with_manifest(&dir).unwrap();
+        let mut manifest = archive.manifest();
+        manifest.nodes.insert(0, ManifestNode { path: "gone.bin".into(), attr: FileAttr::FILE, id: 1 });
+        manifest.write(Manifest::sidecar(&root)).unwrap();
+        std::fs::write(root.join("new.bin"), b"new").unwrap();
+        let imported = import(&root);
+        assert!(!imported.exists("stage/gone.bin"));
+        let names: Vec<_> = imported.root.borrow().children.iter().map(|x| x.borrow().name.clone()).collect();
+        assert_eq!(names, ["a.bin", "jmp", "d.bin", "new.bin", ".", ".."]);
+        let attr = imported.get_file("stage/jmp/b.bcsv").unwrap().borrow().attr;
+        assert_eq!(attr, FileAttr::FILE | FileAttr::LOAD_FROM_DVD);
+        // The node set changed, so ids are numbered afresh.
+        assert_ne!(imported.next_id(), 40);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn broken_manifest_is_an_error() {
+        let dir = temp_dir("manifest_broken");
+        let root = sample().unpack_with_manifest(&dir).unwrap();
+        std::fs::write(Manifest::sidecar(&root), b"{ not json").unwrap();
+        let mut archive = Archive::create("stage", true);
+        assert!(matches!(archive.import(&root, FileAttr::FILE), Err(crate::RarcError::Manifest(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
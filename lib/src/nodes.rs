@@ -1,14 +1,19 @@
 pub mod directory;
 pub mod file;
 
-use super::Reference;
+use super::{Reference, WeakReference};
 
 /// Hash calculation method used for Archive.
 /// This is not the same hash calculation method BCSVs use.
 /// Code inspired by pyjkernel.
 pub const fn calc_hash(name: &str) -> u16 {
+    calc_hash_bytes(name.as_bytes())
+}
+
+/// Ditto of [calc_hash], over a name already in the Archive's
+/// [crate::table::NameEncoding].
+pub const fn calc_hash_bytes(data: &[u8]) -> u16 {
     let mut result = 0u16;
-    let data = name.as_bytes();
     let mut i = 0;
     while i < data.len() {
         let ch = data[i] as u16;
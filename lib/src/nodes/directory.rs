@@ -1,8 +1,11 @@
 use std::path::{Path, PathBuf};
 
 use binrw::prelude::*;
-use super::Reference;
+use super::{Reference, WeakReference};
 use super::file::File;
+use crate::compression::decompress_payload;
+use crate::error::RarcResult;
+use crate::table::NameEncoding;
 
 #[binrw]
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
@@ -21,13 +24,18 @@ pub struct Directory {
     pub node: Node,
     pub is_root: bool,
     pub name: String,
-    pub file: Option<Reference<File>>,
+    /// This Directory's entry in its parent, weak since the parent owns it.
+    pub file: Option<WeakReference<File>>,
     pub children: Vec<Reference<File>>
 }
 
 impl Directory {
     /// Generates this Directory's short name, always a length of 4.
     pub const fn short_name(&self) -> u32 {
+        self.short_name_of(self.name.as_bytes())
+    }
+    /// Ditto of [Directory::short_name], from the name's encoded bytes.
+    const fn short_name_of(&self, bytes: &[u8]) -> u32 {
         if self.is_root {
             #[cfg(target_endian = "little")]
             return u32::from_be_bytes([b'R', b'O', b'O', b'T']);
@@ -35,7 +43,6 @@ impl Directory {
             return u32::from_le_bytes([b'R', b'O', b'O', b'T']);
         }
         let mut result = [b' '; 4];
-        let bytes = self.name.as_bytes();
         let mut i = 0;
         while i < bytes.len() {
             if i >= 4 {break;}
@@ -54,38 +61,41 @@ impl Directory {
     }
     /// Writes this Directory with the given endian.
     pub fn write<W: BinWriterExt>(&self, writer: &mut W, endian: binrw::Endian) -> BinResult<()> {
-        writer.write_type(&self.short_name(), endian)?;
+        self.write_encoded(writer, endian, NameEncoding::Utf8)
+    }
+    /// Ditto of [Directory::write], hashing the name as `encoding` stores it.
+    /// Fails on names `encoding` can't hold.
+    pub fn write_encoded<W: BinWriterExt>(&self, writer: &mut W, endian: binrw::Endian,
+        encoding: NameEncoding) -> BinResult<()> {
+        let name = encoding.encode_at(writer, &self.name)?;
+        writer.write_type(&self.short_name_of(&name), endian)?;
         self.node.name_off.write_options(writer, endian, ())?;
-        let hash = super::calc_hash(&self.name);
+        let hash = super::calc_hash_bytes(&name);
         hash.write_options(writer, endian, ())?;
         let count = self.children.len() as u16;
         count.write_options(writer, endian, ())?;
         self.node.file_off.write_options(writer, endian, ())?;
         Ok(()) 
     }
-    /// to_string is a bit of a bad name, what this really does is generate
-    /// a fullpath name pointing all the way to the root.
-    pub fn to_string(&self) -> String {
-        let mut names = vec![];
-        self.add_name(&mut names);
-        names.reverse();
-        let mut result = PathBuf::new();
-        for name in names {
-            result = result.join(name);
-        }
-        result.to_string_lossy().into()
+    /// This Directory's entry in its parent, if it has one.
+    pub fn get_file(&self) -> Option<Reference<File>> {
+        self.file.as_ref().and_then(WeakReference::upgrade)
     }
     /// Add this Directory's name, then attempts to add the Parent (if it exists).
     pub(crate) fn add_name(&self, names: &mut Vec<String>) {
         names.push(self.name.clone());
-        if let Some(file) = &self.file {
-            if let Some(dir) = file.borrow().parent.as_ref() {
-                dir.borrow().add_name(names);
-            }
+        if let Some(file) = self.get_file()
+            && let Some(dir) = file.borrow().get_parent() {
+            dir.borrow().add_name(names);
         }
     }
     /// Unpack this Directory and **all** children.
-    pub fn unpack<A: AsRef<Path>>(&self, dir: A) -> std::io::Result<()> {
+    pub fn unpack<A: AsRef<Path>>(&self, dir: A) -> RarcResult<()> {
+        self.unpack_with(dir, false)
+    }
+    /// Ditto of [Directory::unpack], optionally decompressing files flagged
+    /// as compressed.
+    pub fn unpack_with<A: AsRef<Path>>(&self, dir: A, decompress: bool) -> RarcResult<()> {
         let dir = dir.as_ref();
         if self.is_root {
             std::fs::create_dir_all(dir.join(&self.name))?;
@@ -102,12 +112,29 @@ impl Directory {
                 false => dir.join(&self.name).join(&child.name)
             };
             if child.is_dir() && let Some(dir) = 
-                &child.folder {
-                dir.borrow().unpack(fullname)?;
+                child.get_folder() {
+                dir.borrow().unpack_with(fullname, decompress)?;
+            } else if child.is_file() && decompress && child.is_compressed() {
+                std::fs::write(fullname, decompress_payload(&child.read_data()?)?)?;
             } else if child.is_file() {
-                std::fs::write(fullname, &child.data)?;
+                std::io::copy(&mut child.open_data()?, &mut std::fs::File::create(fullname)?)?;
             }
         }
         Ok(())
     }
+}
+
+impl std::fmt::Display for Directory {
+    /// to_string is a bit of a bad name, what this really does is generate
+    /// a fullpath name pointing all the way to the root.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names = vec![];
+        self.add_name(&mut names);
+        names.reverse();
+        let mut result = PathBuf::new();
+        for name in names {
+            result = result.join(name);
+        }
+        write!(f, "{}", result.to_string_lossy())
+    }
 }
\ No newline at end of file
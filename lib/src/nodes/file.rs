@@ -1,9 +1,14 @@
 use binrw::prelude::*;
 use bitflags::bitflags;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
 use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use crate::make_reference;
+use crate::table::NameEncoding;
 
-use super::Reference;
+use super::{Reference, WeakReference};
 use super::directory::Directory;
 use std::path::PathBuf;
 
@@ -18,7 +23,7 @@ bitflags! {
         const FILE = 0x1;
         /// Is a folder.
         const FOLDER = 0x2;
-        /// Is compressed in some way.
+        /// Is compressed in some way, Yay0 unless [FileAttr::USE_SZS] is also on.
         const COMPRESSED = 0x4;
         /// Load this to the Main RAM (Default usually).
         const LOAD_TO_MRAM = 0x10;
@@ -59,16 +64,98 @@ pub struct Node {
     pub data_size: u32
 }
 
+/// Anything a [Source::Reader] can read from.
+pub trait SourceReader: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> SourceReader for T {}
+
+#[derive(Clone)]
+/// Where a File's data comes from when it isn't held in [File::data]. Only
+/// read when the Archive is written, one File at a time.
+pub enum Source {
+    /// A file on the host, opened every time it's read.
+    Path(PathBuf),
+    /// Any reader, rewound every time it's read. Clones share the reader.
+    Reader(Arc<Mutex<dyn SourceReader>>)
+}
+
+impl Source {
+    pub fn reader<R: SourceReader + 'static>(reader: R) -> Self {
+        Self::Reader(Arc::new(Mutex::new(reader)))
+    }
+    /// How many bytes reading this gives.
+    pub fn len(&self) -> std::io::Result<u64> {
+        match self {
+            Self::Path(path) => Ok(std::fs::metadata(path)?.len()),
+            Self::Reader(reader) => {
+                let mut reader = reader.lock().unwrap_or_else(PoisonError::into_inner);
+                reader.seek(SeekFrom::End(0))
+            }
+        }
+    }
+    /// Checks if reading this gives nothing.
+    pub fn is_empty(&self) -> std::io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+    /// Opens this from the start.
+    pub fn open(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        match self {
+            Self::Path(path) => Ok(Box::new(std::io::BufReader::new(std::fs::File::open(path)?))),
+            Self::Reader(reader) => {
+                let mut reader = reader.lock().unwrap_or_else(PoisonError::into_inner);
+                reader.rewind()?;
+                Ok(Box::new(LockedReader(reader)))
+            }
+        }
+    }
+}
+
+/// Keeps a [Source::Reader] locked for as long as it's being read.
+struct LockedReader<'a>(MutexGuard<'a, dyn SourceReader + 'static>);
+
+impl Read for LockedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl PartialEq for Source {
+    /// Paths compare by value, readers by identity.
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Path(x), Self::Path(y)) => x == y,
+            (Self::Reader(x), Self::Reader(y)) => Arc::ptr_eq(x, y),
+            _ => false
+        }
+    }
+}
+
+impl Eq for Source {}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            Self::Reader(_) => f.write_str("Reader")
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 /// A File within a Archive. Can point to a folder and have a parent.
+/// Both are weak, the [crate::Archive] owns every Directory.
 pub struct File {
     pub node: Node,
     pub attr: FileAttr,
-    pub folder: Option<Reference<Directory>>,
-    pub parent: Option<Reference<Directory>>,
+    pub folder: Option<WeakReference<Directory>>,
+    pub parent: Option<WeakReference<Directory>>,
     pub name: String,
-    pub name_off: u16,
-    pub data: Vec<u8>
+    pub name_off: u32,
+    pub data: Vec<u8>,
+    /// Read in place of [File::data] when set, so huge files don't have to
+    /// sit in memory unless they're compressed or the whole Archive is.
+    /// [crate::Archive::import] sets this.
+    pub source: Option<Source>
 }
 
 impl File {
@@ -80,6 +167,56 @@ impl File {
     pub const fn is_dir(&self) -> bool {
         self.attr.contains(FileAttr::FOLDER)
     }
+    /// Utility to check if this file's data is stored compressed.
+    pub const fn is_compressed(&self) -> bool {
+        self.attr.contains(FileAttr::COMPRESSED)
+    }
+    /// The Directory this points to, if any and if it still exists.
+    pub fn get_folder(&self) -> Option<Reference<Directory>> {
+        self.folder.as_ref().and_then(WeakReference::upgrade)
+    }
+    /// The Directory this is inside of, if any and if it still exists.
+    pub fn get_parent(&self) -> Option<Reference<Directory>> {
+        self.parent.as_ref().and_then(WeakReference::upgrade)
+    }
+    /// Sets this File's data, dropping any [Source].
+    pub fn set_data(&mut self, data: Vec<u8>) {
+        self.node.data_size = data.len() as u32;
+        self.data = data;
+        self.source = None;
+    }
+    /// Sets where this File's data is read from, dropping [File::data].
+    pub fn set_source(&mut self, source: Source) -> std::io::Result<()> {
+        self.node.data_size = source.len()? as u32;
+        self.data = vec![];
+        self.source = Some(source);
+        Ok(())
+    }
+    /// This File's data, read from its [Source] if it has one.
+    pub fn read_data(&self) -> std::io::Result<Cow<'_, [u8]>> {
+        match &self.source {
+            Some(source) => {
+                let mut data = vec![];
+                source.open()?.read_to_end(&mut data)?;
+                Ok(Cow::Owned(data))
+            },
+            None => Ok(Cow::Borrowed(&self.data))
+        }
+    }
+    /// Size of this File's data, from its [Source] if it has one.
+    pub fn data_len(&self) -> std::io::Result<u64> {
+        match &self.source {
+            Some(source) => source.len(),
+            None => Ok(self.data.len() as u64)
+        }
+    }
+    /// Opens this File's data, without reading it all in if it has a [Source].
+    pub fn open_data(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        match &self.source {
+            Some(source) => source.open(),
+            None => Ok(Box::new(self.data.as_slice()))
+        }
+    }
     /// Checks if this is a dir and the name is "." or ".."
     pub fn is_shortcut(&self) -> bool {
         if self.name == "." || self.name == ".." {
@@ -91,7 +228,7 @@ impl File {
     /// Read this File based off the endian.
     pub fn read<R: BinReaderExt>(&mut self, reader: &mut R, endian: binrw::Endian) -> BinResult<()> {
         self.node = reader.read_type(endian)?; 
-        self.name_off = (self.node.attr_and_off & 0x00FFFFFF) as u16;
+        self.name_off = self.node.attr_and_off & 0x00FFFFFF;
         self.attr = FileAttr((self.node.attr_and_off >> 24) as u8); 
         Ok(())
     }
@@ -99,10 +236,17 @@ impl File {
     /// **NOTE: DOES NOT WRITE THE ACTUAL FILE DATA. THIS ONLY WRITES THE**
     /// **NODE DATA.**
     pub fn write<W: BinWriterExt>(&self, writer: &mut W, endian: binrw::Endian) -> BinResult<()> {
+        self.write_encoded(writer, endian, NameEncoding::Utf8)
+    }
+    /// Ditto of [File::write], hashing the name as `encoding` stores it.
+    /// Fails on names `encoding` can't hold.
+    pub fn write_encoded<W: BinWriterExt>(&self, writer: &mut W, endian: binrw::Endian,
+        encoding: NameEncoding) -> BinResult<()> {
+        let name = encoding.encode_at(writer, &self.name)?;
         writer.write_type(&self.node.id, endian)?;
-        writer.write_type(&super::calc_hash(&self.name), endian)?;
+        writer.write_type(&super::calc_hash_bytes(&name), endian)?;
         let attr = self.attr.0 as u32;
-        let off = self.name_off as u32;
+        let off = self.name_off;
         let total = (attr << 24) | off;
         writer.write_type(&total, endian)?;
         writer.write_type(&self.node.data, endian)?;
@@ -113,11 +257,24 @@ impl File {
         }
         Ok(())
     }
-    /// Ditto of [Directory::to_string].
-    pub fn to_string(&self) -> String {
+    pub fn create(name: &str, attr: FileAttr, folder: Option<Reference<Directory>>, parent: Option<Reference<Directory>>) 
+     -> Reference<File> {
+        let file = File {name: name.into(), attr, folder: folder.as_ref().map(Reference::downgrade),
+            parent: parent.as_ref().map(Reference::downgrade), ..Default::default()};
+        let result = make_reference(file);
+        if let Some(parent) = &parent {
+            parent.borrow_mut().children.push(result.clone());
+        }
+        result
+    }
+}
+
+impl std::fmt::Display for File {
+    /// Ditto of [Directory]'s Display.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut names = vec![];
         names.push(self.name.clone());
-        if let Some(parent) = &self.parent {
+        if let Some(parent) = self.get_parent() {
             parent.borrow().add_name(&mut names);
         }
         names.reverse();
@@ -125,16 +282,48 @@ impl File {
         for name in names {
             result = result.join(name);
         }
-        result.to_string_lossy().into()
+        write!(f, "{}", result.to_string_lossy())
     }
-    
-    pub fn create(name: &str, attr: FileAttr, folder: Option<Reference<Directory>>, parent: Option<Reference<Directory>>) 
-     -> Reference<File> {
-        let file = File {name: name.into(), attr, folder: folder.clone(), parent: parent.clone(), ..Default::default()};
-        let result = make_reference(file);
-        if let Some(parent) = &parent {
-            parent.borrow_mut().children.push(result.clone());
-        }
-        result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::Endian;
+
+    use super::*;
+    use crate::fixture::{read, sample, sample_bytes, temp_dir};
+
+    #[test]
+    fn sources_write_like_data() {
+        let dir = temp_dir("file_sources");
+        std::fs::write(dir.join("d.bin"), [4; 5]).unwrap();
+        let archive = sample();
+        let a = archive.get_file("stage/a.bin").unwrap();
+        a.borrow_mut().set_source(Source::reader(Cursor::new(b"first file".to_vec()))).unwrap();
+        let d = archive.get_file("stage/d.bin").unwrap();
+        d.borrow_mut().set_source(Source::Path(dir.join("d.bin"))).unwrap();
+        assert!(a.borrow().data.is_empty());
+        assert_eq!(d.borrow().node.data_size, 5);
+        assert_eq!(a.borrow().read_data().unwrap(), b"first file".as_slice());
+        // Readers are rewound, writing twice gives the same bytes.
+        assert_eq!(archive.to_bytes(Endian::Big).unwrap(), sample_bytes());
+        assert_eq!(archive.to_bytes(Endian::Big).unwrap(), sample_bytes());
+        d.borrow_mut().set_data(vec![1, 2]);
+        assert_eq!(d.borrow().source, None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn identical_sources_share_data() {
+        let archive = sample();
+        let source = Source::reader(Cursor::new(b"first file".to_vec()));
+        archive.get_file("stage/d.bin").unwrap().borrow_mut().set_source(source).unwrap();
+        let read = read(&archive.to_bytes(Endian::Big).unwrap());
+        let a = read.get_file("stage/a.bin").unwrap();
+        let d = read.get_file("stage/d.bin").unwrap();
+        assert_eq!(a.borrow().data, d.borrow().data);
+        assert_eq!(a.borrow().node.data, d.borrow().node.data);
     }
 }
@@ -0,0 +1,194 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{Archive, FileAttr, Reference, layout::{Layout, Placement}, table::Table};
+use crate::compression::is_compressed_stream;
+
+#[derive(Debug, Default, Clone)]
+/// What [Archive::read] saw besides the headers, so an unedited Archive can be
+/// written back byte for byte. Node name and data offsets live in the nodes
+/// themselves, the section offsets and sizes in [Archive::header] and
+/// [Archive::data_header].
+pub struct PreservedLayout {
+    /// The string table exactly as read, duplicates included.
+    pub table: Table
+}
+
+impl PreservedLayout {
+    /// Checks that every node still agrees with the layout it was read with:
+    /// same names at the same string offsets in the same encoding, same
+    /// attributes, data that still fits its original range and segment, and
+    /// no partially overlapping data.
+    pub fn matches(&self, archive: &Archive) -> bool {
+        let Archive { header, data_header, folders, files, encoding, .. } = archive;
+        if self.table.encoding != *encoding
+            || folders.len() != data_header.dir_node_count as usize
+            || files.len() != data_header.file_node_count as usize {
+            return false;
+        }
+        for folder in folders {
+            let folder = folder.borrow();
+            let (off, count) = (folder.node.file_off as usize, folder.node.file_count as usize);
+            if self.table.get(&folder.node.name_off) != Some(&folder.name)
+                || count != folder.children.len()
+                || off + count > files.len()
+                || !folder.children.iter().zip(&files[off..off + count]).all(|(x, y)| Reference::ptr_eq(x, y)) {
+                return false;
+            }
+        }
+        let mram = header.mram_size;
+        let aram = mram + header.aram_size;
+        let dvd = aram + header.dvd_size;
+        let borrows: Vec<_> = files.iter().map(|x| x.borrow()).collect();
+        let mut ranges = vec![];
+        for file in &borrows {
+            if self.table.get(&file.name_off) != Some(&file.name)
+                || file.node.attr_and_off != ((file.attr.0 as u32) << 24 | file.name_off) {
+                return false;
+            }
+            if file.is_dir() {
+                let index = match file.get_folder() {
+                    Some(folder) => folders.iter().position(|x| Reference::ptr_eq(x, &folder)),
+                    None => None
+                };
+                if index.map_or(u32::MAX, |x| x as u32) != file.node.data {
+                    return false;
+                }
+                continue;
+            }
+            if file.source.is_some() || file.is_compressed() && !is_compressed_stream(&file.data) {
+                return false;
+            }
+            let start = file.node.data;
+            let end = start as u64 + file.data.len() as u64;
+            if file.data.len() as u64 != file.node.data_size as u64 || end > header.file_data_len as u64 {
+                return false;
+            }
+            let segment = if file.attr.contains(FileAttr::LOAD_TO_MRAM) {
+                0..mram
+            } else if file.attr.contains(FileAttr::LOAD_TO_ARAM) {
+                mram..aram
+            } else if file.attr.contains(FileAttr::LOAD_FROM_DVD) {
+                aram..dvd
+            } else {
+                0..header.file_data_len
+            };
+            if dvd != 0 && (start < segment.start || end > segment.end as u64) {
+                return false;
+            }
+            ranges.push((start, end, file.data.as_slice()));
+        }
+        ranges.sort_by_key(|x| (x.0, x.1));
+        let mut seen: HashMap<u32, &[u8]> = HashMap::new();
+        let mut last_end = 0u64;
+        for (start, end, data) in &ranges {
+            if let Some(prev) = seen.get(start) {
+                if prev != data {
+                    return false;
+                }
+                continue;
+            }
+            if (*start as u64) < last_end {
+                return false;
+            }
+            seen.insert(*start, data);
+            last_end = *end;
+        }
+        true
+    }
+}
+
+impl Archive {
+    /// Checks if [Archive::write] will reproduce the layout this Archive was
+    /// read with. Set [Archive::preserved] to [None] to always regenerate it.
+    pub fn preserves_layout(&self) -> bool {
+        self.preserved.as_ref().is_some_and(|x| x.matches(self))
+    }
+
+    /// Puts every node, string and file at the offsets it was read from.
+    /// Only valid when [PreservedLayout::matches] holds.
+    pub(crate) fn preserved_layout(&self, preserved: &PreservedLayout) -> Layout {
+        let Archive { header, data_header, .. } = self;
+        let base = header.data_header_off as u64;
+        let table = base + data_header.string_tbl_off as u64;
+        let start = base + header.file_data_off as u64;
+        let mut data = vec![];
+        let mut seen = HashSet::new();
+        for file in &self.files {
+            let lock = file.borrow();
+            if lock.is_file() && seen.insert(lock.node.data) {
+                data.push(Placement { offset: start + lock.node.data as u64, file: file.clone() });
+            }
+        }
+        let size = [
+            base + 0x20,
+            base + data_header.dir_node_off as u64 + data_header.dir_node_count as u64 * 0x10,
+            base + data_header.file_node_off as u64 + data_header.file_node_count as u64 * 0x14,
+            table + data_header.string_tbl_size as u64,
+            start + header.file_data_len as u64,
+            header.size as u64
+        ].into_iter().max().unwrap_or_default();
+        Layout {
+            header: *header,
+            data_header: *data_header,
+            table: preserved.table.clone(),
+            preserved: true,
+            data,
+            size
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use binrw::Endian;
+
+    use crate::FileAttr;
+    use crate::fixture::{put_u32, read, sample_bytes};
+
+    /// [crate::fixture::sample] padded past what a fresh layout would write,
+    /// so only a preserved layout gives it back.
+    fn padded() -> Vec<u8> {
+        let mut bytes = sample_bytes();
+        bytes.extend([0; 0x20]);
+        let size = bytes.len() as u32;
+        put_u32(&mut bytes, 4, size);
+        bytes
+    }
+
+    #[test]
+    fn unedited_round_trip_is_identical() {
+        let bytes = padded();
+        let archive = read(&bytes);
+        assert!(archive.preserves_layout());
+        assert_eq!(archive.to_bytes(Endian::Big).unwrap(), bytes);
+        let mut fresh = archive.clone();
+        fresh.preserved = None;
+        assert_eq!(fresh.to_bytes(Endian::Big).unwrap(), sample_bytes());
+    }
+
+    #[test]
+    fn edit_falls_back_to_fresh_layout() {
+        let bytes = padded();
+        let mut archive = read(&bytes);
+        archive.add_file("stage/jmp", "e.bin", b"new".to_vec(), FileAttr::LOAD_TO_MRAM).unwrap();
+        assert!(!archive.preserves_layout());
+        let written = archive.to_bytes(Endian::Big).unwrap();
+        assert_ne!(written, bytes);
+        let mut fresh = archive.clone();
+        fresh.preserved = None;
+        assert_eq!(fresh.to_bytes(Endian::Big).unwrap(), written);
+        let reread = read(&written);
+        assert_eq!(reread.get_file("stage/jmp/e.bin").unwrap().borrow().data, b"new");
+        assert_eq!(reread.get_file("stage/jmp/Model/c.bin").unwrap().borrow().data, b"nested");
+    }
+
+    #[test]
+    fn grown_file_falls_back_to_fresh_layout() {
+        let mut archive = read(&padded());
+        archive.replace_data("stage/a.bin", vec![7; 0x40]).unwrap();
+        assert!(!archive.preserves_layout());
+        let reread = read(&archive.to_bytes(Endian::Big).unwrap());
+        assert_eq!(reread.get_file("stage/a.bin").unwrap().borrow().data, [7; 0x40]);
+        assert_eq!(reread.get_file("stage/d.bin").unwrap().borrow().data, [4; 5]);
+    }
+}
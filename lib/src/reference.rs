@@ -0,0 +1,161 @@
+use std::fmt;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+
+/// Shared, thread safe handle to a node. Clones point at the same node.
+/// Only ever points down the tree (or from an [crate::Archive] to what it
+/// owns), links back up are [WeakReference]s so nothing leaks.
+pub struct Reference<T>(Arc<RwLock<T>>);
+
+/// Non owning handle to a node, see [Reference].
+pub struct WeakReference<T>(Weak<RwLock<T>>);
+
+impl<T> Reference<T> {
+    pub fn new(item: T) -> Self {
+        Self(Arc::new(RwLock::new(item)))
+    }
+    /// Locks the node for reading. Several threads may read at once, but a
+    /// thread must not lock a node it already holds: std's RwLock may deadlock
+    /// or panic on recursive reads. Clone out what's needed and release first.
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Locks the node for writing. Blocks until every other lock is released.
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Checks if both handles point at the same node.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+    pub fn downgrade(&self) -> WeakReference<T> {
+        WeakReference(Arc::downgrade(&self.0))
+    }
+}
+
+impl<T> WeakReference<T> {
+    /// The node, unless whatever owned it was dropped.
+    pub fn upgrade(&self) -> Option<Reference<T>> {
+        self.0.upgrade().map(Reference)
+    }
+    /// Checks if both handles point at the same node.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Weak::ptr_eq(&this.0, &other.0)
+    }
+}
+
+impl<T> Clone for Reference<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Clone for WeakReference<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: Default> Default for Reference<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Default for WeakReference<T> {
+    fn default() -> Self {
+        Self(Weak::new())
+    }
+}
+
+impl<T: PartialEq> PartialEq for Reference<T> {
+    /// Compares the nodes themselves, like `Rc<RefCell<T>>` did.
+    fn eq(&self, other: &Self) -> bool {
+        Self::ptr_eq(self, other) || *self.borrow() == *other.borrow()
+    }
+}
+
+impl<T: Eq> Eq for Reference<T> {}
+
+impl<T> PartialEq for WeakReference<T> {
+    /// Weak links compare by identity, comparing contents could walk back up
+    /// and around the whole tree.
+    fn eq(&self, other: &Self) -> bool {
+        Self::ptr_eq(self, other)
+    }
+}
+
+impl<T> Eq for WeakReference<T> {}
+
+impl<T: fmt::Debug> fmt::Debug for Reference<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.try_read() {
+            Ok(item) => item.fmt(f),
+            Err(_) => f.write_str("<locked>")
+        }
+    }
+}
+
+impl<T> fmt::Debug for WeakReference<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(Weak)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::{Archive, fixture::sample};
+
+    #[test]
+    fn archives_are_send_and_sync() {
+        fn check<T: Send + Sync>() {}
+        check::<Archive>();
+        let archive = sample();
+        let names = || archive.files.iter().map(|x| x.borrow().name.clone()).collect::<Vec<_>>();
+        let elsewhere = std::thread::scope(|s| s.spawn(names).join().unwrap());
+        assert_eq!(elsewhere, names());
+    }
+
+    #[test]
+    fn dropping_the_archive_frees_the_tree() {
+        let archive = sample();
+        let root = archive.root.downgrade();
+        let file = archive.get_file("stage/jmp/Model/c.bin").unwrap().downgrade();
+        assert!(root.upgrade().is_some());
+        drop(archive);
+        assert!(root.upgrade().is_none());
+        assert!(file.upgrade().is_none());
+    }
+
+    #[test]
+    fn walks_never_lock_a_node_twice() {
+        // A walk reading a folder again while it holds one of its children
+        // deadlocks as soon as a writer queues up in between.
+        let archive = Arc::new(sample());
+        let (done, finished) = std::sync::mpsc::channel();
+        let reader = archive.clone();
+        std::thread::spawn(move || {
+            for _ in 0..2000 {
+                reader.find_by_glob("stage/**/*.bin").unwrap();
+                reader.find_files_by_name("c.bin");
+                reader.find_dirs_by_name("Model");
+                reader.folder_collisions();
+                reader.hash_collisions();
+            }
+            done.send(()).unwrap();
+        });
+        let folders = archive.folders.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let writing = stop.clone();
+        std::thread::spawn(move || while !writing.load(Ordering::Relaxed) {
+            for folder in &folders {
+                drop(folder.borrow_mut());
+            }
+        });
+        let result = finished.recv_timeout(std::time::Duration::from_secs(60));
+        stop.store(true, Ordering::Relaxed);
+        assert!(result.is_ok(), "walking the tree deadlocked");
+    }
+}
@@ -0,0 +1,172 @@
+use glob::Pattern;
+
+use crate::FileAttr;
+use crate::error::{RarcError, RarcResult};
+use crate::iter::{GLOB_OPTIONS, compile_glob};
+
+/// Flags saying where a file loads, a rule without one keeps the default's.
+const LOAD_FLAGS: FileAttr = FileAttr(FileAttr::LOAD_TO_MRAM.0 | FileAttr::LOAD_TO_ARAM.0 | FileAttr::LOAD_FROM_DVD.0);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What an [AttrRule] picks files by.
+pub enum RuleMatch {
+    /// A glob over the path below the imported dir, `/` separated. Globs
+    /// without a `/` match the file name alone.
+    Glob(Pattern),
+    /// A file extension without the dot, ignoring case.
+    Extension(String)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Gives imported files it matches `attr` instead of the default, see
+/// [crate::archive::ImportOptions::rules].
+pub struct AttrRule {
+    pub matches: RuleMatch,
+    pub attr: FileAttr
+}
+
+impl AttrRule {
+    pub fn glob(pattern: &str, attr: FileAttr) -> RarcResult<Self> {
+        Ok(Self { matches: RuleMatch::Glob(compile_glob(pattern)?), attr })
+    }
+    pub fn extension(extension: &str, attr: FileAttr) -> Self {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        Self { matches: RuleMatch::Extension(extension), attr }
+    }
+    /// Parses `pattern=attributes`, e.g. `*.ast=dvd` or `Model/**=mram,yaz0`.
+    /// A pattern like `.ast`, a dot and no glob chars, is an extension. See
+    /// [parse_attr] for the attributes.
+    pub fn parse(rule: &str) -> RarcResult<Self> {
+        let Some((pattern, attr)) = rule.rsplit_once('=') else {
+            return Err(RarcError::BadPattern { pattern: rule.into(), message: "expected pattern=attributes" });
+        };
+        let (pattern, attr) = (pattern.trim(), parse_attr(attr)?);
+        match pattern.strip_prefix('.') {
+            Some(extension) if !extension.is_empty() && !extension.contains(['*', '?', '[', '/', '.']) =>
+                Ok(Self::extension(extension, attr)),
+            _ => Self::glob(pattern, attr)
+        }
+    }
+    /// Parses a rules file, one [AttrRule::parse] rule per line. Blank lines
+    /// and lines starting with `#` are skipped.
+    pub fn parse_list(text: &str) -> RarcResult<Vec<Self>> {
+        text.lines()
+            .map(str::trim)
+            .filter(|x| !x.is_empty() && !x.starts_with('#'))
+            .map(Self::parse)
+            .collect()
+    }
+    /// Checks if this matches the file at `path`, below the imported dir and
+    /// `/` separated.
+    pub fn is_match(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        match &self.matches {
+            RuleMatch::Glob(pattern) if pattern.as_str().contains('/') => pattern.matches_with(path, GLOB_OPTIONS),
+            RuleMatch::Glob(pattern) => pattern.matches_with(name, GLOB_OPTIONS),
+            RuleMatch::Extension(extension) => name.rsplit_once('.')
+                .is_some_and(|x| x.1.eq_ignore_ascii_case(extension))
+        }
+    }
+}
+
+/// Parses comma separated attributes: where to load, `mram`, `aram` or
+/// `dvd`, and how the file's compressed, `yaz0` or `yay0`. [FileAttr::FILE]
+/// is always on.
+pub fn parse_attr(attrs: &str) -> RarcResult<FileAttr> {
+    let mut result = FileAttr::FILE;
+    for attr in attrs.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+        result |= match attr.to_ascii_lowercase().as_str() {
+            "mram" => FileAttr::LOAD_TO_MRAM,
+            "aram" => FileAttr::LOAD_TO_ARAM,
+            "dvd" => FileAttr::LOAD_FROM_DVD,
+            "yaz0" | "szs" => FileAttr::COMPRESSED | FileAttr::USE_SZS,
+            "yay0" | "szp" => FileAttr::COMPRESSED,
+            _ => return Err(RarcError::BadPattern { pattern: attrs.into(),
+                message: "unknown attribute, expected mram, aram, dvd, yaz0 or yay0" })
+        };
+    }
+    if (result & LOAD_FLAGS).bits().count_ones() > 1 {
+        return Err(RarcError::BadPattern { pattern: attrs.into(), message: "more than one of mram, aram and dvd" });
+    }
+    Ok(result)
+}
+
+/// The attributes of the first rule matching `path`, `default` if none do.
+/// A rule that doesn't say where to load keeps where `default` loads.
+pub fn attr_for(rules: &[AttrRule], path: &str, default: FileAttr) -> FileAttr {
+    match rules.iter().find(|x| x.is_match(path)) {
+        Some(rule) if (rule.attr & LOAD_FLAGS).is_empty() => rule.attr | (default & LOAD_FLAGS),
+        Some(rule) => rule.attr,
+        None => default
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Archive;
+    use crate::archive::ImportOptions;
+    use crate::fixture::temp_dir;
+    use super::*;
+
+    const MRAM: FileAttr = FileAttr(FileAttr::FILE.0 | FileAttr::LOAD_TO_MRAM.0);
+    const DVD: FileAttr = FileAttr(FileAttr::FILE.0 | FileAttr::LOAD_FROM_DVD.0);
+
+    #[test]
+    fn rules_parse() {
+        assert_eq!(AttrRule::parse(".AST = dvd").unwrap(), AttrRule::extension("ast", DVD));
+        assert_eq!(AttrRule::parse("*.ast=dvd").unwrap(), AttrRule::glob("*.ast", DVD).unwrap());
+        assert_eq!(AttrRule::parse("Model/**=mram,yaz0").unwrap().attr,
+            MRAM | FileAttr::COMPRESSED | FileAttr::USE_SZS);
+        assert_eq!(AttrRule::parse("*.arc=szp").unwrap().attr, FileAttr::FILE | FileAttr::COMPRESSED);
+        // A dot with glob chars or a path is a glob, not an extension.
+        assert!(matches!(AttrRule::parse(".a*=dvd").unwrap().matches, RuleMatch::Glob(_)));
+        let rules = AttrRule::parse_list("# sounds\n\n  .ast=dvd\n*.bcsv = aram  \n").unwrap();
+        assert_eq!(rules.len(), 2);
+    }
+
+    #[test]
+    fn malformed_rules_fail() {
+        for rule in ["*.ast", "*.ast=cd", "*.ast=mram,dvd", "[a=dvd", ".ast=yaz0,mram,aram"] {
+            assert!(matches!(AttrRule::parse(rule), Err(RarcError::BadPattern { .. })), "{}", rule);
+        }
+        assert!(AttrRule::parse_list(".ast=dvd\n*.bcsv").is_err());
+    }
+
+    #[test]
+    fn globs_and_extensions_match() {
+        let name = AttrRule::parse("c*.bin=dvd").unwrap();
+        assert!(name.is_match("c.bin") && name.is_match("jmp/Model/c.bin"));
+        assert!(!name.is_match("jmp/cat/a.bin"));
+        let path = AttrRule::parse("jmp/**/*.bin=dvd").unwrap();
+        assert!(path.is_match("jmp/Model/c.bin") && !path.is_match("c.bin"));
+        let extension = AttrRule::parse(".bin=dvd").unwrap();
+        assert!(extension.is_match("jmp/A.BIN") && !extension.is_match("bin") && !extension.is_match("a.bin.bak"));
+    }
+
+    #[test]
+    fn first_rule_wins() {
+        let rules = AttrRule::parse_list("jmp/**=aram\n.bin=dvd\n*=yaz0").unwrap();
+        assert_eq!(attr_for(&rules, "jmp/c.bin", MRAM), FileAttr::FILE | FileAttr::LOAD_TO_ARAM);
+        assert_eq!(attr_for(&rules, "c.bin", MRAM), DVD);
+        // Without a load flag the default's is kept.
+        assert_eq!(attr_for(&rules, "d.txt", MRAM), MRAM | FileAttr::COMPRESSED | FileAttr::USE_SZS);
+        assert_eq!(attr_for(&[], "d.txt", MRAM), MRAM);
+    }
+
+    #[test]
+    fn import_applies_rules() {
+        let dir = temp_dir("rules_import");
+        std::fs::create_dir_all(dir.join("stage/jmp")).unwrap();
+        std::fs::write(dir.join("stage/a.bin"), b"a").unwrap();
+        std::fs::write(dir.join("stage/jmp/b.bcsv"), b"b").unwrap();
+        std::fs::write(dir.join("stage/jmp/c.ast"), b"c").unwrap();
+        let mut archive = Archive::create("stage", true);
+        let options = ImportOptions { rules: AttrRule::parse_list("jmp/*.bcsv=aram\n.ast=dvd").unwrap(), ..Default::default() };
+        archive.import_with(dir.join("stage"), &options).unwrap();
+        let attr = |path| archive.get_file(path).unwrap().borrow().attr;
+        assert_eq!(attr("stage/a.bin"), MRAM);
+        assert_eq!(attr("stage/jmp/b.bcsv"), FileAttr::FILE | FileAttr::LOAD_TO_ARAM);
+        assert_eq!(attr("stage/jmp/c.ast"), DVD);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
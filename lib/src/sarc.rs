@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::io::{Read, SeekFrom, Write};
+
+use binrw::{Endian, prelude::*};
+
+use crate::{Archive, FileAttr, Reference};
+use crate::error::{RarcError, RarcResult};
+use crate::nodes::{Directory, File};
+
+/// Magic at the start of every SARC archive.
+pub const MAGIC: &[u8; 4] = b"SARC";
+
+/// SARC has no root, every name is a path. Read Archives get a root of this
+/// name and written ones store paths below their root.
+pub const ROOT_NAME: &str = "sarc";
+
+/// Multiplier [calc_hash] uses, stored in every written SFAT.
+pub const HASH_KEY: u32 = 0x65;
+
+/// Every file's data starts on a multiple of this.
+pub const ALIGNMENT: u32 = 0x80;
+
+const HEADER_SIZE: u16 = 0x14;
+const SFAT_HEADER_SIZE: u16 = 0xC;
+const SFNT_HEADER_SIZE: u16 = 0x8;
+/// Set in [Node::attr] when the node has a name in the SFNT.
+const HAS_NAME: u32 = 0x01000000;
+
+#[binrw]
+#[derive(Debug, Default, Clone, Copy)]
+/// The header following [MAGIC], its size and the byte order mark.
+pub struct Header {
+    pub file_size: u32,
+    pub data_off: u32,
+    pub version: u16,
+    pub reserved: u16
+}
+
+#[binrw]
+#[brw(magic = b"SFAT")]
+#[derive(Debug, Default, Clone, Copy)]
+/// Heads the file table, right after [Header].
+pub struct SfatHeader {
+    pub header_size: u16,
+    pub node_count: u16,
+    pub hash_key: u32
+}
+
+#[binrw]
+#[derive(Debug, Default, Clone, Copy)]
+/// A SARC file, there are no folders.
+pub struct Node {
+    pub hash: u32,
+    /// [HAS_NAME] and the name's offset in the SFNT divided by 4.
+    pub attr: u32,
+    /// Both relative to [Header::data_off].
+    pub data_start: u32,
+    pub data_end: u32
+}
+
+/// Name hash used to sort and look up SFAT nodes. Each byte is sign extended
+/// like Nintendo's tools do.
+pub const fn calc_hash(name: &[u8], key: u32) -> u32 {
+    let mut result = 0u32;
+    let mut i = 0;
+    while i < name.len() {
+        result = result.wrapping_mul(key).wrapping_add(name[i] as i8 as u32);
+        i += 1;
+    }
+    result
+}
+
+impl Archive {
+    /// Reads a SARC archive into this Archive, see [ROOT_NAME]. Folders are
+    /// made from the paths and files get [FileAttr::LOAD_TO_MRAM] since SARC
+    /// has no attributes. Nameless nodes are named after their hash.
+    pub fn read_sarc<R: BinReaderExt>(&mut self, reader: &mut R) -> RarcResult<()> {
+        let start = reader.stream_position()?;
+        let mut head = [0u8; 8];
+        reader.read_exact(&mut head)
+            .map_err(|_| RarcError::Truncated { section: "SARC header", offset: start })?;
+        if &head[..4] != MAGIC {
+            return Err(RarcError::BadMagic { offset: start, magic: [head[0], head[1], head[2], head[3]] });
+        }
+        let endian = match [head[6], head[7]] {
+            [0xFE, 0xFF] => Endian::Big,
+            [0xFF, 0xFE] => Endian::Little,
+            bom => return Err(RarcError::BadMagic { offset: start + 4, magic: [head[4], head[5], bom[0], bom[1]] })
+        };
+        let header: Header = reader.read_type(endian)
+            .map_err(RarcError::section("SARC header", start))?;
+        let off = reader.stream_position()?;
+        let stream_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(off))?;
+        let sfat: SfatHeader = reader.read_type(endian)
+            .map_err(RarcError::section("SFAT", off))?;
+        reader.seek(SeekFrom::Start(off + sfat.header_size as u64))?;
+        // Offsets and counts come from the header, don't allocate for what
+        // can't be there.
+        if off + sfat.header_size as u64 + sfat.node_count as u64 * 16 > stream_len {
+            return Err(RarcError::Truncated { section: "SFAT node", offset: off });
+        }
+        let mut nodes = Vec::with_capacity(sfat.node_count as usize);
+        for _ in 0..sfat.node_count {
+            let off = reader.stream_position()?;
+            let node: Node = reader.read_type(endian)
+                .map_err(RarcError::section("SFAT node", off))?;
+            nodes.push(node);
+        }
+        let off = reader.stream_position()?;
+        let mut sfnt = [0u8; 8];
+        reader.read_exact(&mut sfnt)
+            .map_err(|_| RarcError::Truncated { section: "SFNT", offset: off })?;
+        if &sfnt[..4] != b"SFNT" {
+            return Err(RarcError::BadMagic { offset: off, magic: [sfnt[0], sfnt[1], sfnt[2], sfnt[3]] });
+        }
+        let table_off = off + SFNT_HEADER_SIZE as u64;
+        let data_off = start + header.data_off as u64;
+        if data_off > stream_len {
+            return Err(RarcError::Truncated { section: "SFNT", offset: table_off });
+        }
+        let mut table = vec![0u8; data_off.saturating_sub(table_off) as usize];
+        reader.read_exact(&mut table)
+            .map_err(|_| RarcError::Truncated { section: "SFNT", offset: table_off })?;
+        *self = Archive {
+            encoding: self.encoding,
+            compression: self.compression,
+            ..Archive::create(ROOT_NAME, true)
+        };
+        let mut folders = HashMap::new();
+        for (i, node) in nodes.iter().enumerate() {
+            let path = match node.attr & HAS_NAME {
+                0 => format!("{:08X}", node.hash),
+                _ => {
+                    let name_off = (node.attr & 0x00FFFFFF) * 4;
+                    let bytes = table.get(name_off as usize..)
+                        .and_then(|x| x.split(|x| *x == 0).next())
+                        .ok_or(RarcError::BadNameOffset { node: i, offset: name_off })?;
+                    self.encoding.decode(bytes)
+                        .ok_or(RarcError::InvalidEncoding { offset: name_off })?
+                }
+            };
+            let (dir, name) = match path.rsplit_once('/') {
+                Some((dir, name)) => (self.sarc_folder(&mut folders, dir)?, name),
+                None => (self.root.clone(), path.as_str())
+            };
+            if dir.borrow().children.iter().any(|x| x.borrow().name == name) {
+                return Err(RarcError::AlreadyExists { path });
+            }
+            let off = data_off + node.data_start as u64;
+            if data_off + node.data_end as u64 > stream_len {
+                return Err(RarcError::Truncated { section: "file data", offset: off });
+            }
+            let mut data = vec![0u8; node.data_end.saturating_sub(node.data_start) as usize];
+            reader.seek(SeekFrom::Start(off))?;
+            reader.read_exact(&mut data)
+                .map_err(|_| RarcError::Truncated { section: "file data", offset: off })?;
+            let file = self.create_file(name, FileAttr::FILE | FileAttr::LOAD_TO_MRAM, Some(dir));
+            file.borrow_mut().set_data(data);
+        }
+        self.sort_unchecked()
+    }
+
+    /// The folder at `path` below the root, made along with its parents if
+    /// it doesn't exist yet.
+    fn sarc_folder(&mut self, folders: &mut HashMap<String, Reference<Directory>>, path: &str)
+        -> RarcResult<Reference<Directory>> {
+        if let Some(folder) = folders.get(path) {
+            return Ok(folder.clone());
+        }
+        let (parent, name) = match path.rsplit_once('/') {
+            Some((dir, name)) => (self.sarc_folder(folders, dir)?, name),
+            None => (self.root.clone(), path)
+        };
+        if parent.borrow().children.iter().any(|x| x.borrow().name == name) {
+            return Err(RarcError::AlreadyExists { path: path.into() });
+        }
+        let folder = self.create_folder(name, Some(parent));
+        folders.insert(path.into(), folder.clone());
+        Ok(folder)
+    }
+
+    /// Writes this Archive as SARC, strictly front to back. Only files are
+    /// stored, by their path below the root. Attributes and ids are dropped,
+    /// file data is written as is.
+    pub fn write_sarc<W: Write>(&self, writer: &mut W, endian: Endian) -> RarcResult<()> {
+        self.check_names()?;
+        let mut files = vec![];
+        collect_sarc(&self.root, "", &mut files)?;
+        let mut entries = files.into_iter().map(|(path, file)| {
+            let name = self.encoding.encode(&path).unwrap_or(path.as_bytes().into()).into_owned();
+            (calc_hash(&name, HASH_KEY), name, file)
+        }).collect::<Vec<_>>();
+        entries.sort_by(|x, y| (x.0, &x.1).cmp(&(y.0, &y.1)));
+        let mut table = vec![];
+        let mut nodes = vec![];
+        let mut end = 0u32;
+        for (hash, name, file) in &entries {
+            let start = end.next_multiple_of(ALIGNMENT);
+            end = start + file.borrow().data_len()? as u32;
+            nodes.push(Node { hash: *hash, attr: HAS_NAME | (table.len() as u32 / 4), data_start: start, data_end: end });
+            table.extend_from_slice(name);
+            table.push(0);
+            table.resize(table.len().next_multiple_of(4), 0);
+        }
+        let names_off = HEADER_SIZE as u32 + SFAT_HEADER_SIZE as u32 + nodes.len() as u32 * 0x10
+            + SFNT_HEADER_SIZE as u32;
+        let data_off = (names_off + table.len() as u32).next_multiple_of(ALIGNMENT);
+        let mut head = std::io::Cursor::new(vec![]);
+        head.write_all(MAGIC)?;
+        HEADER_SIZE.write_options(&mut head, endian, ())?;
+        0xFEFFu16.write_options(&mut head, endian, ())?;
+        Header { file_size: data_off + end, data_off, version: 0x100, reserved: 0 }
+            .write_options(&mut head, endian, ())?;
+        SfatHeader { header_size: SFAT_HEADER_SIZE, node_count: nodes.len() as u16, hash_key: HASH_KEY }
+            .write_options(&mut head, endian, ())?;
+        for node in &nodes {
+            node.write_options(&mut head, endian, ())?;
+        }
+        head.write_all(b"SFNT")?;
+        SFNT_HEADER_SIZE.write_options(&mut head, endian, ())?;
+        0u16.write_options(&mut head, endian, ())?;
+        head.write_all(&table)?;
+        let mut head = head.into_inner();
+        head.resize(data_off as usize, 0);
+        writer.write_all(&head)?;
+        let mut pos = 0;
+        for ((_, _, file), node) in entries.iter().zip(&nodes) {
+            writer.write_all(&vec![0u8; (node.data_start - pos) as usize])?;
+            let size = (node.data_end - node.data_start) as u64;
+            if std::io::copy(&mut file.borrow().open_data()?.take(size), writer)? != size {
+                return Err(RarcError::Truncated { section: "file data", offset: (data_off + node.data_start) as u64 });
+            }
+            pos = node.data_end;
+        }
+        Ok(())
+    }
+}
+
+/// Adds every file inside `folder` to `files` with its path below the root.
+fn collect_sarc(folder: &Reference<Directory>, prefix: &str, files: &mut Vec<(String, Reference<File>)>)
+    -> RarcResult<()> {
+    for child in &folder.borrow().children {
+        let lock = child.borrow();
+        if lock.is_shortcut() {
+            continue;
+        }
+        let path = format!("{}{}", prefix, lock.name);
+        if lock.is_dir() {
+            let folder = lock.get_folder()
+                .ok_or_else(|| RarcError::UnlinkedDirectory { name: lock.name.clone() })?;
+            drop(lock);
+            collect_sarc(&folder, &format!("{}/", path), files)?;
+        } else if lock.is_file() {
+            files.push((path, child.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fixture::sample;
+
+    fn to_sarc(archive: &Archive, endian: Endian) -> Vec<u8> {
+        let mut bytes = vec![];
+        archive.write_sarc(&mut bytes, endian).unwrap();
+        bytes
+    }
+
+    fn try_read(bytes: &[u8]) -> RarcResult<Archive> {
+        let mut archive = Archive::default();
+        archive.read_sarc(&mut Cursor::new(bytes))?;
+        Ok(archive)
+    }
+
+    #[test]
+    fn round_trip() {
+        for endian in [Endian::Big, Endian::Little] {
+            let bytes = to_sarc(&sample(), endian);
+            let archive = try_read(&bytes).unwrap();
+            assert_eq!(archive.root.borrow().name, ROOT_NAME);
+            assert_eq!(archive.get_file("sarc/a.bin").unwrap().borrow().data, b"first file");
+            assert_eq!(archive.get_file("sarc/jmp/b.bcsv").unwrap().borrow().data, vec![0xAB; 0x30]);
+            assert_eq!(archive.get_file("sarc/jmp/Model/c.bin").unwrap().borrow().data, b"nested");
+            assert_eq!(to_sarc(&archive, endian), bytes);
+        }
+    }
+
+    #[test]
+    fn bad_input_fails() {
+        let mut bytes = to_sarc(&sample(), Endian::Little);
+        for len in [0, 4, 0x14, 0x20, bytes.len() - 1] {
+            assert!(try_read(&bytes[..len]).is_err(), "cut at {:#x}", len);
+        }
+        assert!(matches!(try_read(&bytes[..0x10]), Err(RarcError::Truncated { .. })));
+        bytes[0] = b'R';
+        assert!(matches!(try_read(&bytes), Err(RarcError::BadMagic { .. })));
+    }
+
+    #[test]
+    fn forged_offsets_fail() {
+        let bytes = to_sarc(&sample(), Endian::Little);
+        // Data offset, node count and the first node's data end.
+        for (off, value, section) in [(0xC, 0xFFFF_FFF0, "SFNT"), (0x1A, 0xFFFF, "SFAT node"), (0x2C, 0xFFFF_FFF0, "file data")] {
+            let mut bytes = bytes.clone();
+            let width = if off == 0x1A { 2 } else { 4 };
+            bytes[off..off + width].copy_from_slice(&u32::to_le_bytes(value)[..width]);
+            assert!(matches!(try_read(&bytes), Err(RarcError::Truncated { section: s, .. }) if s == section),
+                "{} at {:#x}", section, off);
+        }
+    }
+}
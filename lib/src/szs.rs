@@ -0,0 +1,157 @@
+use crate::compression::{Level, default_threads};
+use crate::error::{RarcError, RarcResult};
+use crate::lz::{self, Token};
+
+/// Magic at the start of every Yaz0 (SZS) stream.
+pub const MAGIC: &[u8; 4] = b"Yaz0";
+
+/// Size of the Yaz0 header: magic, decompressed size, alignment and padding.
+const HEADER_SIZE: usize = 0x10;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// The fields of a Yaz0 header after its magic.
+pub struct Header {
+    /// Size of the data once decompressed.
+    pub size: u32,
+    /// Alignment the data wants once decompressed, 0 on GameCube and most Wii
+    /// files.
+    pub alignment: u32
+}
+
+impl Header {
+    /// Reads the header at the start of `data`.
+    pub fn read(data: &[u8]) -> RarcResult<Self> {
+        if !data.starts_with(MAGIC) {
+            let mut magic = [0u8; 4];
+            let len = data.len().min(4);
+            magic[..len].copy_from_slice(&data[..len]);
+            return Err(RarcError::BadMagic { offset: 0, magic });
+        }
+        let Some(fields) = data.get(4..12) else {
+            return Err(RarcError::Truncated { section: "Yaz0 header", offset: data.len() as u64 });
+        };
+        Ok(Self {
+            size: u32::from_be_bytes([fields[0], fields[1], fields[2], fields[3]]),
+            alignment: u32::from_be_bytes([fields[4], fields[5], fields[6], fields[7]])
+        })
+    }
+}
+
+fn read_u8(data: &[u8], off: usize) -> RarcResult<u8> {
+    data.get(off).copied()
+        .ok_or(RarcError::Truncated { section: "Yaz0 stream", offset: off as u64 })
+}
+
+/// Decompresses a whole Yaz0 stream.
+pub fn decompress(data: &[u8]) -> RarcResult<Vec<u8>> {
+    let size = Header::read(data)?.size as usize;
+    let mut off = HEADER_SIZE;
+    let mut result = Vec::with_capacity(lz::reserve(size, data.len()));
+    let (mut code, mut bits) = (0u8, 0);
+    while result.len() < size {
+        if bits == 0 {
+            code = read_u8(data, off)?;
+            off += 1;
+            bits = 8;
+        }
+        if code & 0x80 != 0 {
+            result.push(read_u8(data, off)?);
+            off += 1;
+        } else {
+            let pair = (read_u8(data, off)? as usize) << 8 | read_u8(data, off + 1)? as usize;
+            let dist = (pair & 0xFFF) + 1;
+            if dist > result.len() {
+                return Err(RarcError::BadCompressedData { format: "Yaz0", offset: off as u64 });
+            }
+            let len = match pair >> 12 {
+                0 => {
+                    off += 3;
+                    read_u8(data, off - 1)? as usize + 0x12
+                },
+                n => {
+                    off += 2;
+                    n + 2
+                }
+            };
+            for _ in 0..len {
+                result.push(result[result.len() - dist]);
+            }
+        }
+        code <<= 1;
+        bits -= 1;
+    }
+    result.truncate(size);
+    Ok(result)
+}
+
+/// Compresses `data` into a Yaz0 stream using every available thread.
+pub fn compress(data: &[u8], level: Level) -> Vec<u8> {
+    compress_with(data, level, default_threads())
+}
+
+/// Compresses `data` into a Yaz0 stream, searching with up to `threads`
+/// threads. The output is the same for any thread count.
+pub fn compress_with(data: &[u8], level: Level, threads: usize) -> Vec<u8> {
+    let mut result = Vec::with_capacity(0x10 + data.len() + data.len() / 8 + 1);
+    result.extend_from_slice(MAGIC);
+    result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    result.extend_from_slice(&[0u8; 8]);
+    let (mut code, mut bits) = (0, 8);
+    lz::tokenize(data, level, threads, |token| {
+        if bits == 8 {
+            code = result.len();
+            result.push(0);
+            bits = 0;
+        }
+        match token {
+            Token::Literal(byte) => {
+                result[code] |= 0x80 >> bits;
+                result.push(byte);
+            },
+            Token::Match { dist, len } if len < 0x12 => {
+                let pair = (len - 2) << 12 | (dist - 1);
+                result.extend_from_slice(&pair.to_be_bytes());
+            },
+            Token::Match { dist, len } => {
+                result.extend_from_slice(&(dist - 1).to_be_bytes());
+                result.push((len - 0x12) as u8);
+            }
+        }
+        bits += 1;
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lz::checks::{self, Codec};
+
+    const CODEC: Codec = Codec { compress: compress_with, decompress };
+
+    #[test]
+    fn round_trip_every_level() {
+        checks::round_trip_every_level(CODEC);
+    }
+
+    #[test]
+    fn same_output_for_any_thread_count() {
+        checks::same_output_for_any_thread_count(CODEC);
+    }
+
+    #[test]
+    fn known_stream() {
+        let expected = [b"Yaz0\0\0\0\x0a".as_slice(), &[0; 8], &[0x80, b'a', 0x70, 0x00]].concat();
+        assert_eq!(compress(b"aaaaaaaaaa", Level::default()), expected);
+        assert_eq!(decompress(&expected).unwrap(), b"aaaaaaaaaa");
+    }
+
+    #[test]
+    fn corrupt_streams_fail() {
+        checks::truncated_streams_fail(CODEC);
+        assert!(matches!(decompress(b"Yay0\0\0\0\x01"), Err(RarcError::BadMagic { .. })));
+        // A match reaching back before the start of the output.
+        let back = [b"Yaz0\0\0\0\x03".as_slice(), &[0; 8], &[0x00, 0x10, 0x00]].concat();
+        assert!(matches!(decompress(&back), Err(RarcError::BadCompressedData { .. })));
+    }
+}
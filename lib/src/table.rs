@@ -1,15 +1,88 @@
+use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
+use std::io::Seek;
 use std::ops::*;
 use super::archive::Archive;
+use super::error::{RarcError, RarcResult};
 
 use binrw::{NullString, prelude::*};
+use encoding_rs::SHIFT_JIS;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// How node names are stored in the string table. Names are always Unicode
+/// in memory and on disk, this only changes the bytes inside the Archive.
+pub enum NameEncoding {
+    #[default]
+    Utf8,
+    /// Shift-JIS, used by Japanese retail Archives.
+    ShiftJis,
+    /// Every byte becomes the char with the same value (Latin-1), so names in
+    /// any other encoding still round trip.
+    Raw
+}
+
+impl NameEncoding {
+    /// Decodes a name as stored in the table. Fails if `bytes` isn't valid or
+    /// wouldn't encode back to the same bytes.
+    pub fn decode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Self::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Self::ShiftJis => {
+                let name = SHIFT_JIS.decode_without_bom_handling_and_without_replacement(bytes)?;
+                match self.encode(&name)? == bytes {
+                    true => Some(name.into_owned()),
+                    false => None
+                }
+            },
+            Self::Raw => Some(bytes.iter().map(|x| *x as char).collect())
+        }
+    }
+    /// Encodes a name to be stored in the table, [None] if it has chars this
+    /// encoding can't hold.
+    pub fn encode(self, name: &str) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Self::Utf8 => Some(Cow::Borrowed(name.as_bytes())),
+            Self::ShiftJis => match SHIFT_JIS.encode(name) {
+                (bytes, _, false) => Some(bytes),
+                _ => None
+            },
+            Self::Raw => name.chars()
+                .map(|x| u8::try_from(x).ok())
+                .collect::<Option<Vec<_>>>()
+                .map(Cow::Owned)
+        }
+    }
+    /// Ditto of [NameEncoding::encode] for node writers, failing with a
+    /// [RarcError::UnencodableName] at `writer`'s position.
+    pub(crate) fn encode_at<'a, W: Seek>(self, writer: &mut W, name: &'a str) -> BinResult<Cow<'a, [u8]>> {
+        match self.encode(name) {
+            Some(bytes) => Ok(bytes),
+            None => Err(binrw::Error::Custom {
+                pos: writer.stream_position()?,
+                err: Box::new(RarcError::UnencodableName { name: name.into(), encoding: self })
+            })
+        }
+    }
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct Table {
-    pub table: BTreeMap<u32, String>
+    pub table: BTreeMap<u32, String>,
+    /// Used to turn names into bytes, offsets are in encoded bytes.
+    pub encoding: NameEncoding
 }
 
 impl Table {
+    pub fn new(encoding: NameEncoding) -> Self {
+        Self { table: BTreeMap::new(), encoding }
+    }
+    /// `str` as stored in this table, falls back to UTF-8 if it can't be
+    /// encoded. [Archive::write] rejects such names before getting here.
+    pub fn encoded<'a>(&self, str: &'a str) -> Cow<'a, [u8]> {
+        self.encoding.encode(str).unwrap_or(Cow::Borrowed(str.as_bytes()))
+    }
     pub fn lookup(&self) -> HashMap<String, u32> {
         let mut result = HashMap::new();
         for (key, str) in &self.table {
@@ -18,9 +91,9 @@ impl Table {
         result
     }
     pub fn write<W: BinWriterExt>(&self, writer: &mut W) -> BinResult<()> {
-        for (_, str) in &self.table {
-            let string = NullString::from(str.as_str());
-            string.write_ne(writer)?;
+        for str in self.table.values() {
+            writer.write_all(&self.encoded(str))?;
+            0u8.write_ne(writer)?;
         }
         let offset = writer.stream_position()?.next_multiple_of(32);
         while writer.stream_position()? < offset {
@@ -31,8 +104,8 @@ impl Table {
     
     pub fn total_size(&self) -> u32 {
         let mut total = 0u32;
-        for (_, str) in &self.table {
-            total += str.len() as u32 + 1;
+        for str in self.table.values() {
+            total += self.encoded(str).len() as u32 + 1;
         }
         total.next_multiple_of(32)
     }
@@ -44,7 +117,7 @@ impl Table {
         } else {
             if let Some((&loff, lstr)) = self.table.iter().last() {
                 let mut off = loff;
-                off += (lstr.len() + 1) as u32;
+                off += (self.encoded(lstr).len() + 1) as u32;
                 self.table.insert(off, item);
                 off
             } else {
@@ -69,17 +142,29 @@ impl DerefMut for Table {
 }
 
 impl Archive {
-    pub fn read_table<R: BinReaderExt>(&mut self, reader: &mut R) -> BinResult<Table> {
-        let mut result = Table::default();
-        let current = reader.stream_position()?;
+    pub fn read_table<R: BinReaderExt>(&mut self, reader: &mut R) -> RarcResult<Table> {
         let offset = self.data_header.string_tbl_off + self.header.data_header_off;
-        let start = reader.seek(std::io::SeekFrom::Start(offset as _))?;
-        let end = start + self.data_header.string_tbl_size as u64;
+        Table::read(reader, offset as u64, self.data_header.string_tbl_size, self.encoding)
+    }
+}
+
+impl Table {
+    /// Reads the `size` bytes long table at `offset`, restoring the reader's
+    /// position afterwards.
+    pub fn read<R: BinReaderExt>(reader: &mut R, offset: u64, size: u32, encoding: NameEncoding)
+        -> RarcResult<Self> {
+        let mut result = Table::new(encoding);
+        let current = reader.stream_position()?;
+        let start = reader.seek(std::io::SeekFrom::Start(offset))?;
+        let end = start + size as u64;
         let mut off = 0u32;
         while reader.stream_position()? < end {
-            let ne = NullString::read_ne(reader)?;
-            let str = String::from_utf8(ne.0).unwrap();
-            let len = str.len() as u32;
+            let pos = reader.stream_position()?;
+            let ne = NullString::read_ne(reader)
+                .map_err(RarcError::section("string table", pos))?;
+            let len = ne.0.len() as u32;
+            let str = encoding.decode(&ne.0)
+                .ok_or(RarcError::InvalidEncoding { offset: off })?;
             if str.is_empty() {break;}
             result.table.insert(off, str);
             off += len + 1;
@@ -87,4 +172,68 @@ impl Archive {
         reader.seek(std::io::SeekFrom::Start(current))?;
         Ok(result)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::Endian;
+
+    use super::*;
+    use crate::fixture::{sample, sample_bytes};
+
+    fn read_as(bytes: &[u8], encoding: NameEncoding) -> RarcResult<Archive> {
+        let mut archive = Archive { encoding, ..Default::default() };
+        archive.read(&mut Cursor::new(bytes))?;
+        Ok(archive)
+    }
+
+    fn find(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+        bytes.windows(needle.len()).position(|x| x == needle)
+    }
+
+    #[test]
+    fn shift_jis_names_round_trip() {
+        let mut archive = sample();
+        archive.encoding = NameEncoding::ShiftJis;
+        archive.rename("stage/a.bin", "ステージ.bin").unwrap();
+        let bytes = archive.to_bytes(Endian::Big).unwrap();
+        let encoded = NameEncoding::ShiftJis.encode("ステージ.bin").unwrap();
+        assert_ne!(&*encoded, "ステージ.bin".as_bytes());
+        assert!(find(&bytes, &encoded).is_some());
+        assert!(find(&bytes, "ステージ".as_bytes()).is_none());
+        let read = read_as(&bytes, NameEncoding::ShiftJis).unwrap();
+        assert!(read.get_file("stage/ステージ.bin").is_ok());
+        assert_eq!(read.to_bytes(Endian::Big).unwrap(), bytes);
+        assert!(matches!(read_as(&bytes, NameEncoding::Utf8), Err(RarcError::InvalidEncoding { .. })));
+    }
+
+    #[test]
+    fn raw_keeps_any_bytes() {
+        let mut bytes = sample_bytes();
+        let off = find(&bytes, b"a.bin\0").unwrap();
+        bytes[off] = 0xE9;
+        assert!(matches!(read_as(&bytes, NameEncoding::Utf8), Err(RarcError::InvalidEncoding { .. })));
+        let archive = read_as(&bytes, NameEncoding::Raw).unwrap();
+        assert!(archive.get_file("stage/\u{E9}.bin").is_ok());
+        assert_eq!(archive.to_bytes(Endian::Big).unwrap(), bytes);
+    }
+
+    #[test]
+    fn unencodable_names_are_errors() {
+        let mut archive = sample();
+        archive.encoding = NameEncoding::Raw;
+        archive.rename("stage/a.bin", "ステージ.bin").unwrap();
+        assert!(matches!(archive.to_bytes(Endian::Big), Err(RarcError::UnencodableName { .. })));
+        assert_eq!(NameEncoding::ShiftJis.encode("\u{1F600}"), None);
+        // Nodes written on their own fail too instead of falling back to UTF-8.
+        let file = archive.get_file("stage/ステージ.bin").unwrap();
+        let result = file.borrow().write_encoded(&mut Cursor::new(vec![]), Endian::Big, NameEncoding::Raw);
+        assert!(matches!(result.map_err(RarcError::from), Err(RarcError::UnencodableName { .. })));
+        let folder = archive.get_dir("stage/jmp").unwrap();
+        folder.borrow_mut().name = "ジャンプ".into();
+        let result = folder.borrow().write_encoded(&mut Cursor::new(vec![]), Endian::Big, NameEncoding::Raw);
+        assert!(matches!(result.map_err(RarcError::from), Err(RarcError::UnencodableName { .. })));
+    }
+}
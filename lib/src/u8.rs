@@ -0,0 +1,278 @@
+use std::io::{Read, SeekFrom, Write};
+
+use binrw::prelude::*;
+
+use crate::{Archive, FileAttr, Reference};
+use crate::error::{RarcError, RarcResult};
+use crate::nodes::{Directory, File};
+
+/// Magic at the start of every U8 archive.
+pub const MAGIC: &[u8; 4] = &[0x55, 0xAA, 0x38, 0x2D];
+
+/// Where the nodes start, right after [Header].
+const ROOT_OFFSET: u32 = 0x20;
+
+/// U8 has a nameless root, the [Archive] root is its only folder. A U8 with
+/// anything else at the top gets a root of this name holding all of it.
+pub const ROOT_NAME: &str = "arc";
+
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Default, Clone, Copy)]
+/// The header following [MAGIC]. Always big endian.
+pub struct Header {
+    pub root_off: u32,
+    /// Size of the nodes and string table together.
+    pub header_size: u32,
+    pub data_off: u32,
+    pub reserved: [u8; 16]
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Default, Clone, Copy)]
+/// A U8 file or folder.
+pub struct Node {
+    /// Folder flag in the top byte, name offset in the rest.
+    pub kind_and_off: u32,
+    /// Files: where the data starts. Folders: index of the parent.
+    pub data: u32,
+    /// Files: size of the data. Folders: index of the first node after it.
+    pub size: u32
+}
+
+impl Node {
+    pub const fn is_dir(&self) -> bool {
+        self.kind_and_off >> 24 == 1
+    }
+    pub const fn name_off(&self) -> u32 {
+        self.kind_and_off & 0x00FFFFFF
+    }
+}
+
+/// Name at `off` in the string table, up to its terminator.
+fn table_name(archive: &Archive, table: &[u8], off: u32) -> RarcResult<String> {
+    let bytes = table.get(off as usize..)
+        .and_then(|x| x.split(|x| *x == 0).next())
+        .ok_or(RarcError::BadNameOffset { node: 0, offset: off })?;
+    archive.encoding.decode(bytes).ok_or(RarcError::InvalidEncoding { offset: off })
+}
+
+impl Archive {
+    /// Reads a U8 archive into this Archive, see [ROOT_NAME] for how its root
+    /// is mapped. Files get [FileAttr::LOAD_TO_MRAM] since U8 has no
+    /// attributes.
+    pub fn read_u8<R: BinReaderExt>(&mut self, reader: &mut R) -> RarcResult<()> {
+        let start = reader.stream_position()?;
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)
+            .map_err(|_| RarcError::Truncated { section: "U8 header", offset: start })?;
+        if &magic != MAGIC {
+            return Err(RarcError::BadMagic { offset: start, magic });
+        }
+        let header: Header = reader.read_be()
+            .map_err(RarcError::section("U8 header", start))?;
+        let stream_len = reader.seek(SeekFrom::End(0))?;
+        let root_off = start + header.root_off as u64;
+        // Sizes come from the header, don't allocate for what can't be there.
+        if root_off + header.header_size as u64 > stream_len {
+            return Err(RarcError::Truncated { section: "U8 string table", offset: root_off });
+        }
+        reader.seek(SeekFrom::Start(root_off))?;
+        let root: Node = reader.read_be()
+            .map_err(RarcError::section("U8 node", root_off))?;
+        if root_off + root.size as u64 * 12 > stream_len {
+            return Err(RarcError::Truncated { section: "U8 node", offset: root_off });
+        }
+        let mut nodes = vec![root];
+        for i in 1..root.size as u64 {
+            let off = root_off + i * 12;
+            nodes.push(reader.read_be().map_err(RarcError::section("U8 node", off))?);
+        }
+        let table_size = (header.header_size as usize).saturating_sub(nodes.len() * 12);
+        let mut table = vec![0u8; table_size];
+        let table_off = reader.stream_position()?;
+        reader.read_exact(&mut table)
+            .map_err(|_| RarcError::Truncated { section: "U8 string table", offset: table_off })?;
+        let names = nodes.iter().enumerate()
+            .map(|(i, x)| table_name(self, &table, x.name_off()).map_err(|e| match e {
+                RarcError::BadNameOffset { offset, .. } => RarcError::BadNameOffset { node: i, offset },
+                e => e
+            }))
+            .collect::<RarcResult<Vec<_>>>()?;
+        let top = match nodes.get(1) {
+            Some(node) if node.is_dir() && node.size == root.size => 2,
+            _ => 1
+        };
+        let name = match top {
+            2 => names[1].as_str(),
+            _ => ROOT_NAME
+        };
+        *self = Archive {
+            encoding: self.encoding,
+            compression: self.compression,
+            ..Archive::create(name, true)
+        };
+        let mut parents = vec![(root.size, self.root.clone())];
+        for (i, node) in nodes.iter().enumerate().skip(top) {
+            while parents.last().is_some_and(|x| i as u32 >= x.0) {
+                parents.pop();
+            }
+            let parent = parents.last().map(|x| x.1.clone());
+            if node.is_dir() {
+                if node.size <= i as u32 || node.size > root.size {
+                    return Err(RarcError::BadFileIndex { node: i, index: node.size });
+                }
+                let folder = self.create_folder(&names[i], parent);
+                parents.push((node.size, folder));
+            } else {
+                let file = self.create_file(&names[i], FileAttr::FILE | FileAttr::LOAD_TO_MRAM, parent);
+                let off = start + node.data as u64;
+                if off + node.size as u64 > stream_len {
+                    return Err(RarcError::Truncated { section: "file data", offset: off });
+                }
+                let mut data = vec![0u8; node.size as usize];
+                reader.seek(SeekFrom::Start(off))?;
+                reader.read_exact(&mut data)
+                    .map_err(|_| RarcError::Truncated { section: "file data", offset: off })?;
+                file.borrow_mut().set_data(data);
+            }
+        }
+        self.sort_unchecked()
+    }
+
+    /// Writes this Archive as U8, strictly front to back. The root becomes
+    /// the only folder in U8's nameless root. Attributes and ids are dropped,
+    /// file data is written as is.
+    pub fn write_u8<W: Write>(&self, writer: &mut W) -> RarcResult<()> {
+        self.check_names()?;
+        let mut nodes = vec![Node { kind_and_off: 1 << 24, ..Default::default() }];
+        let mut table = vec![0u8];
+        let mut files = vec![];
+        collect_u8(self, &self.root, 0, &mut nodes, &mut table, &mut files)?;
+        nodes[0].size = nodes.len() as u32;
+        let header_size = nodes.len() as u32 * 12 + table.len() as u32;
+        let data_off = (ROOT_OFFSET + header_size).next_multiple_of(0x20);
+        let mut off = data_off;
+        for (index, file) in &files {
+            let size = file.borrow().data_len()? as u32;
+            nodes[*index].data = off;
+            nodes[*index].size = size;
+            off = (off + size).next_multiple_of(0x20);
+        }
+        let mut head = std::io::Cursor::new(vec![]);
+        head.write_all(MAGIC)?;
+        Header { root_off: ROOT_OFFSET, header_size, data_off, reserved: [0; 16] }.write_be(&mut head)?;
+        for node in &nodes {
+            node.write_be(&mut head)?;
+        }
+        head.write_all(&table)?;
+        let mut head = head.into_inner();
+        head.resize(data_off as usize, 0);
+        writer.write_all(&head)?;
+        let mut pos = data_off as u64;
+        for (index, file) in &files {
+            let size = nodes[*index].size as u64;
+            let file = file.borrow();
+            if std::io::copy(&mut file.open_data()?.take(size), writer)? != size {
+                return Err(RarcError::Truncated { section: "file data", offset: pos });
+            }
+            let end = (pos + size).next_multiple_of(0x20);
+            writer.write_all(&vec![0u8; (end - pos - size) as usize])?;
+            pos = end;
+        }
+        Ok(())
+    }
+}
+
+/// Adds `folder` and everything in it to `nodes` depth first, U8 needs every
+/// folder's nodes right after it. Files are noted with their node index so
+/// their data can be placed once every node is known.
+fn collect_u8(archive: &Archive, folder: &Reference<Directory>, parent: u32, nodes: &mut Vec<Node>,
+    table: &mut Vec<u8>, files: &mut Vec<(usize, Reference<File>)>) -> RarcResult<()> {
+    let index = nodes.len();
+    let folder = folder.borrow();
+    nodes.push(Node { kind_and_off: 1 << 24 | add_name(archive, table, &folder.name), data: parent, size: 0 });
+    for child in &folder.children {
+        let lock = child.borrow();
+        if lock.is_shortcut() {
+            continue;
+        }
+        if lock.is_dir() {
+            let folder = lock.get_folder()
+                .ok_or_else(|| RarcError::UnlinkedDirectory { name: lock.name.clone() })?;
+            drop(lock);
+            collect_u8(archive, &folder, index as u32, nodes, table, files)?;
+        } else if lock.is_file() {
+            files.push((nodes.len(), child.clone()));
+            nodes.push(Node { kind_and_off: add_name(archive, table, &lock.name), ..Default::default() });
+        }
+    }
+    nodes[index].size = nodes.len() as u32;
+    Ok(())
+}
+
+/// Appends `name` to the string table, returning its offset.
+fn add_name(archive: &Archive, table: &mut Vec<u8>, name: &str) -> u32 {
+    let off = table.len() as u32;
+    table.extend_from_slice(&archive.encoding.encode(name).unwrap_or(name.as_bytes().into()));
+    table.push(0);
+    off
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fixture::{put_u32, sample};
+
+    fn to_u8(archive: &Archive) -> Vec<u8> {
+        let mut bytes = vec![];
+        archive.write_u8(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn try_read(bytes: &[u8]) -> RarcResult<Archive> {
+        let mut archive = Archive::default();
+        archive.read_u8(&mut Cursor::new(bytes))?;
+        Ok(archive)
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = to_u8(&sample());
+        let archive = try_read(&bytes).unwrap();
+        assert_eq!(archive.root.borrow().name, "stage");
+        assert_eq!(archive.get_file("stage/a.bin").unwrap().borrow().data, b"first file");
+        assert_eq!(archive.get_file("stage/jmp/b.bcsv").unwrap().borrow().data, vec![0xAB; 0x30]);
+        assert_eq!(archive.get_file("stage/jmp/Model/c.bin").unwrap().borrow().data, b"nested");
+        assert_eq!(to_u8(&archive), bytes);
+    }
+
+    #[test]
+    fn bad_input_fails() {
+        let mut bytes = to_u8(&sample());
+        // The last byte is padding, cut into the first file's data instead.
+        let data_off = u32::from_be_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]) as usize;
+        for len in [0, 2, 0x10, 0x30, data_off + 1] {
+            assert!(try_read(&bytes[..len]).is_err(), "cut at {:#x}", len);
+        }
+        assert!(matches!(try_read(&bytes[..0x10]), Err(RarcError::Truncated { .. })));
+        bytes[0] = b'R';
+        assert!(matches!(try_read(&bytes), Err(RarcError::BadMagic { .. })));
+    }
+
+    #[test]
+    fn oversized_header_fields_fail() {
+        let bytes = to_u8(&sample());
+        let root = ROOT_OFFSET as usize;
+        let file = (root..).step_by(12).find(|x| bytes[*x] == 0).unwrap();
+        for (off, section) in [(8, "U8 string table"), (root + 8, "U8 node"), (file + 8, "file data")] {
+            let mut bytes = bytes.clone();
+            put_u32(&mut bytes, off, 0xFFFF_FFF0);
+            assert!(matches!(try_read(&bytes), Err(RarcError::Truncated { section: s, .. }) if s == section),
+                "{} at {:#x}", section, off);
+        }
+    }
+}
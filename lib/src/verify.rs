@@ -0,0 +1,599 @@
+use std::collections::{HashMap, hash_map::Entry};
+use std::fmt;
+use std::io::SeekFrom;
+
+use binrw::prelude::*;
+
+use crate::FileAttr;
+use crate::compression::is_compressed_stream;
+use crate::error::RarcResult;
+use crate::header;
+use crate::nodes::{calc_hash_bytes, directory, file};
+use crate::table::NameEncoding;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// How bad an [Issue] is.
+pub enum Severity {
+    /// Unusual but loads fine.
+    Warning,
+    /// The game will likely misread or crash on this.
+    Error
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Something [verify] found wrong with an Archive.
+pub struct Issue {
+    pub severity: Severity,
+    /// Where in the Archive, when it's down to one spot.
+    pub offset: Option<u64>,
+    pub message: String
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error"
+        };
+        match self.offset {
+            Some(offset) => write!(f, "{} at {:#x}: {}", severity, offset, self.message),
+            None => write!(f, "{}: {}", severity, self.message)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Every [Issue] found, in the order they were found.
+pub struct Report {
+    pub issues: Vec<Issue>
+}
+
+impl Report {
+    pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|x| x.severity == Severity::Error)
+    }
+    pub fn warnings(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|x| x.severity == Severity::Warning)
+    }
+    /// Checks if nothing is an error, warnings are fine.
+    pub fn is_ok(&self) -> bool {
+        self.errors().next().is_none()
+    }
+    fn error<S: Into<String>>(&mut self, offset: Option<u64>, message: S) {
+        self.issues.push(Issue { severity: Severity::Error, offset, message: message.into() });
+    }
+    fn warn<S: Into<String>>(&mut self, offset: Option<u64>, message: S) {
+        self.issues.push(Issue { severity: Severity::Warning, offset, message: message.into() });
+    }
+}
+
+/// The raw string table, names are checked as bytes so a bad encoding can't
+/// hide anything else.
+struct Names<'a> {
+    table: &'a [u8],
+    encoding: NameEncoding
+}
+
+impl Names<'_> {
+    /// The name starting at `off`, if `off` is the start of a string.
+    fn get(&self, off: u32) -> Option<&[u8]> {
+        let off = off as usize;
+        if off >= self.table.len() || (off != 0 && self.table[off - 1] != 0) {
+            return None;
+        }
+        self.table[off..].split(|x| *x == 0).next()
+    }
+    fn show(&self, name: &[u8]) -> String {
+        self.encoding.decode(name).unwrap_or_else(|| String::from_utf8_lossy(name).into_owned())
+    }
+}
+
+/// Checks an Archive's internal consistency without building it: headers
+/// against the stream length, names and hashes, node links, the "." and ".."
+/// of every folder, and where file data lies. Only an unreadable header is
+/// an `Err`, everything else ends up in the [Report].
+pub fn verify<R: BinReaderExt>(reader: &mut R, encoding: NameEncoding) -> RarcResult<Report> {
+    let mut report = Report::default();
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let (endian, header, data_header) = header::read_headers(reader)?;
+    let base = header.data_header_off as u64;
+    if header.size as u64 > len {
+        report.error(Some(0x4), format!("header says the archive is {} bytes but it's {}", header.size, len));
+    } else if (header.size as u64) < len {
+        report.warn(Some(0x4), format!("{} bytes past the size in the header", len - header.size as u64));
+    }
+    let segments = header.mram_size as u64 + header.aram_size as u64 + header.dvd_size as u64;
+    if segments != header.file_data_len as u64 {
+        report.error(Some(0xC), format!("MRAM, ARAM and DVD sizes add up to {} but file data is {} bytes",
+            segments, header.file_data_len));
+    }
+    let sections = [
+        ("directory nodes", data_header.dir_node_off, data_header.dir_node_count as u64 * 0x10),
+        ("file nodes", data_header.file_node_off, data_header.file_node_count as u64 * 0x14),
+        ("string table", data_header.string_tbl_off, data_header.string_tbl_size as u64),
+        ("file data", header.file_data_off, header.file_data_len as u64)
+    ];
+    let mut readable = true;
+    for (name, off, size) in sections {
+        let start = base + off as u64;
+        if start + size > len {
+            report.error(Some(start), format!("{} run past the end of the archive", name));
+            readable &= name == "file data";
+        }
+    }
+    if !readable {
+        return Ok(report);
+    }
+    let table_off = base + data_header.string_tbl_off as u64;
+    reader.seek(SeekFrom::Start(table_off))?;
+    let mut table = vec![0u8; data_header.string_tbl_size as usize];
+    reader.read_exact(&mut table)?;
+    let names = Names { table: &table, encoding };
+    let dir_off = base + data_header.dir_node_off as u64;
+    reader.seek(SeekFrom::Start(dir_off))?;
+    let mut folders = vec![];
+    for _ in 0..data_header.dir_node_count {
+        folders.push(reader.read_type::<directory::Node>(endian)?);
+    }
+    let file_off = base + data_header.file_node_off as u64;
+    reader.seek(SeekFrom::Start(file_off))?;
+    let mut files = vec![];
+    for _ in 0..data_header.file_node_count {
+        files.push(reader.read_type::<file::Node>(endian)?);
+        reader.seek(SeekFrom::Current(4))?;
+    }
+    let mut folder_names = vec![];
+    for (i, folder) in folders.iter().enumerate() {
+        let off = Some(dir_off + i as u64 * 0x10);
+        let Some(name) = names.get(folder.name_off) else {
+            report.error(off, format!("directory {} has name offset {:#x} outside the string table",
+                i, folder.name_off));
+            folder_names.push(format!("#{}", i));
+            continue;
+        };
+        folder_names.push(names.show(name));
+        if folder.hash != calc_hash_bytes(name) {
+            report.error(off, format!("directory {:?} has hash {:#06x}, its name hashes to {:#06x}",
+                folder_names[i], folder.hash, calc_hash_bytes(name)));
+        }
+    }
+    let mut file_names = vec![];
+    let mut raw_names = vec![];
+    for (i, file) in files.iter().enumerate() {
+        let off = Some(file_off + i as u64 * 0x14);
+        let name_off = file.attr_and_off & 0x00FFFFFF;
+        let Some(name) = names.get(name_off) else {
+            report.error(off, format!("file node {} has name offset {:#x} outside the string table", i, name_off));
+            file_names.push((format!("#{}", i), false));
+            raw_names.push(None);
+            continue;
+        };
+        file_names.push((names.show(name), name == b"." || name == b".."));
+        raw_names.push(Some(name));
+        if file.hash != calc_hash_bytes(name) {
+            report.error(off, format!("file node {:?} has hash {:#06x}, its name hashes to {:#06x}",
+                file_names[i].0, file.hash, calc_hash_bytes(name)));
+        }
+    }
+    let mut parents = vec![None; folders.len()];
+    let mut links = vec![0; folders.len()];
+    for (i, folder) in folders.iter().enumerate() {
+        let off = Some(dir_off + i as u64 * 0x10);
+        let start = folder.file_off as usize;
+        let end = start + folder.file_count as usize;
+        if end > files.len() {
+            report.error(off, format!("directory {:?} has children {}..{} but there are {} file nodes",
+                folder_names[i], start, end, files.len()));
+            continue;
+        }
+        let mut hashes = HashMap::new();
+        for index in start..end {
+            let Some(name) = raw_names[index] else {
+                continue;
+            };
+            let off = Some(file_off + index as u64 * 0x14);
+            match hashes.entry(calc_hash_bytes(name)) {
+                Entry::Vacant(entry) => { entry.insert(index); },
+                Entry::Occupied(entry) if raw_names[*entry.get()] == Some(name) => if !file_names[index].1 {
+                    report.error(off, format!("{:?} is in {:?} twice", file_names[index].0, folder_names[i]));
+                },
+                Entry::Occupied(entry) => report.error(off, format!("{:?} and {:?} in {:?} both hash to {:#06x}",
+                    file_names[*entry.get()].0, file_names[index].0, folder_names[i], entry.key()))
+            }
+        }
+        let (mut dots, mut dotdots) = (0, 0);
+        for index in start..end {
+            let node = &files[index];
+            let attr = FileAttr((node.attr_and_off >> 24) as u8);
+            if !attr.contains(FileAttr::FOLDER) || node.data as usize >= folders.len() {
+                if file_names[index].0 == "." {
+                    dots += 1;
+                } else if file_names[index].0 == ".." {
+                    dotdots += 1;
+                }
+                continue;
+            }
+            match file_names[index].0.as_str() {
+                "." => {
+                    dots += 1;
+                    if node.data as usize != i {
+                        report.error(Some(file_off + index as u64 * 0x14), format!(
+                            "\".\" in {:?} points to directory {}", folder_names[i], node.data));
+                    }
+                },
+                ".." => dotdots += 1,
+                _ => {
+                    parents[node.data as usize].get_or_insert(i);
+                    links[node.data as usize] += 1;
+                }
+            }
+        }
+        if dots != 1 {
+            report.error(off, format!("directory {:?} has {} \".\" entries instead of 1", folder_names[i], dots));
+        }
+        if dotdots != 1 {
+            report.error(off, format!("directory {:?} has {} \"..\" entries instead of 1", folder_names[i], dotdots));
+        }
+    }
+    for (i, folder) in folders.iter().enumerate() {
+        let start = folder.file_off as usize;
+        let end = (start + folder.file_count as usize).min(files.len());
+        for index in start.min(end)..end {
+            let node = &files[index];
+            if file_names[index].0 != ".." || (node.attr_and_off >> 24) as u8 & FileAttr::FOLDER.0 == 0 {
+                continue;
+            }
+            let expected = parents[i].map_or(u32::MAX, |x| x as u32);
+            if node.data != expected {
+                report.error(Some(file_off + index as u64 * 0x14), format!(
+                    "\"..\" in {:?} points to directory {:#x} instead of {:#x}", folder_names[i], node.data, expected));
+            }
+        }
+        if i != 0 && links[i] == 0 {
+            report.warn(Some(dir_off + i as u64 * 0x10), format!("directory {:?} isn't inside any folder", folder_names[i]));
+        } else if links[i] > 1 {
+            report.warn(Some(dir_off + i as u64 * 0x10), format!("directory {:?} is inside {} folders", folder_names[i], links[i]));
+        }
+    }
+    let mram = header.mram_size as u64;
+    let aram = mram + header.aram_size as u64;
+    let dvd = aram + header.dvd_size as u64;
+    let data_off = base + header.file_data_off as u64;
+    let mut ranges = vec![];
+    for (i, node) in files.iter().enumerate() {
+        let off = Some(file_off + i as u64 * 0x14);
+        let attr = FileAttr((node.attr_and_off >> 24) as u8);
+        let name = &file_names[i].0;
+        if attr.contains(FileAttr::FOLDER) {
+            if attr.contains(FileAttr::FILE) {
+                report.error(off, format!("{:?} is flagged both file and folder", name));
+            }
+            if node.data as usize >= folders.len() && !(node.data == u32::MAX && file_names[i].1) {
+                report.error(off, format!("folder node {:?} points to directory {} which doesn't exist", name, node.data));
+            }
+            continue;
+        }
+        if !attr.contains(FileAttr::FILE) {
+            report.warn(off, format!("{:?} is flagged neither file nor folder", name));
+            continue;
+        }
+        if data_header.sync && node.id != i as u16 {
+            report.error(off, format!("{:?} has id {} but ids are synced to node indices", name, node.id));
+        }
+        let start = node.data as u64;
+        let end = start + node.data_size as u64;
+        let segment = if attr.contains(FileAttr::LOAD_TO_MRAM) {
+            Some(("MRAM", 0..mram))
+        } else if attr.contains(FileAttr::LOAD_TO_ARAM) {
+            Some(("ARAM", mram..aram))
+        } else if attr.contains(FileAttr::LOAD_FROM_DVD) {
+            Some(("DVD", aram..dvd))
+        } else {
+            report.warn(off, format!("{:?} isn't flagged MRAM, ARAM or DVD", name));
+            None
+        };
+        if end > header.file_data_len as u64 {
+            report.error(off, format!("{:?} data {:#x}..{:#x} runs past the file data", name, start, end));
+            continue;
+        }
+        if let Some((segment, range)) = segment
+            && (start < range.start || end > range.end) && node.data_size != 0 {
+            report.error(off, format!("{:?} data {:#x}..{:#x} is outside the {} segment {:#x}..{:#x}",
+                name, start, end, segment, range.start, range.end));
+        }
+        if attr.contains(FileAttr::COMPRESSED) {
+            let mut magic = [0u8; 4];
+            reader.seek(SeekFrom::Start(data_off + start))?;
+            if node.data_size < 4 || reader.read_exact(&mut magic).is_err() || !is_compressed_stream(&magic) {
+                report.warn(off, format!("{:?} is flagged compressed but isn't Yaz0 or Yay0", name));
+            }
+        }
+        ranges.push((start, end, i));
+    }
+    if data_header.sync && data_header.next_idx as usize != files.len() {
+        report.warn(Some(base + 0x18), format!("next_idx is {} but there are {} file nodes",
+            data_header.next_idx, files.len()));
+    }
+    ranges.sort();
+    let mut last: Option<(u64, u64, usize)> = None;
+    for (start, end, i) in ranges {
+        if let Some((lstart, lend, li)) = last && start < lend {
+            let off = Some(file_off + i as u64 * 0x14);
+            if start == lstart && end == lend {
+                continue;
+            } else if start == lstart || end <= lend {
+                report.warn(off, format!("{:?} data lies within the data of {:?}", file_names[i].0, file_names[li].0));
+                continue;
+            } else {
+                report.error(off, format!("{:?} data partially overlaps the data of {:?}", file_names[i].0, file_names[li].0));
+            }
+        }
+        last = Some((start, end, i));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::fixture::{put_u32, read, sample_bytes};
+
+    fn get_u32(bytes: &[u8], off: usize) -> u32 {
+        u32::from_be_bytes(bytes[off..off + 4].try_into().unwrap())
+    }
+
+    /// [sample_bytes] and where its nodes are, to corrupt them by hand.
+    struct Corrupt {
+        bytes: Vec<u8>,
+        names: Vec<String>
+    }
+
+    impl Corrupt {
+        fn new() -> Self {
+            let bytes = sample_bytes();
+            let names = read(&bytes).files.iter().map(|x| x.borrow().name.clone()).collect();
+            Self { bytes, names }
+        }
+        fn dir(&self, folder: usize) -> usize {
+            0x20 + get_u32(&self.bytes, 0x24) as usize + folder * 0x10
+        }
+        /// File node `name` inside directory `folder`.
+        fn file(&self, folder: usize, name: &str) -> usize {
+            let first = get_u32(&self.bytes, self.dir(folder) + 0xC) as usize;
+            let index = (first..).find(|x| self.names[*x] == name).unwrap();
+            0x20 + get_u32(&self.bytes, 0x2C) as usize + index * 0x14
+        }
+        fn add(&mut self, off: usize, value: u32) {
+            let value = get_u32(&self.bytes, off).wrapping_add(value);
+            put_u32(&mut self.bytes, off, value);
+        }
+        fn set_attr(&mut self, node: usize, attr: FileAttr) {
+            self.bytes[node + 4] = attr.0;
+        }
+        /// Checks verify finds an issue of `severity` saying `message`.
+        fn finds(&self, severity: Severity, message: &str) {
+            let report = verify(&mut Cursor::new(&self.bytes), NameEncoding::Utf8).unwrap();
+            assert!(report.issues.iter().any(|x| x.severity == severity && x.message.contains(message)),
+                "no {:?} {:?} in {:#?}", severity, message, report.issues);
+            if severity == Severity::Error {
+                assert!(!report.is_ok());
+            }
+        }
+    }
+
+    const A: FileAttr = FileAttr(FileAttr::FILE.0 | FileAttr::LOAD_TO_MRAM.0);
+
+    #[test]
+    fn clean_archive_has_no_issues() {
+        let report = verify(&mut Cursor::new(sample_bytes()), NameEncoding::Utf8).unwrap();
+        assert_eq!(report, Report::default());
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn unreadable_header_is_an_err() {
+        assert!(verify(&mut Cursor::new(&sample_bytes()[..0x30]), NameEncoding::Utf8).is_err());
+    }
+
+    #[test]
+    fn size_past_the_end() {
+        let mut corrupt = Corrupt::new();
+        corrupt.add(0x4, 1);
+        corrupt.finds(Severity::Error, "header says");
+    }
+
+    #[test]
+    fn bytes_past_the_size() {
+        let mut corrupt = Corrupt::new();
+        corrupt.bytes.extend([0; 0x20]);
+        corrupt.finds(Severity::Warning, "past the size in the header");
+    }
+
+    #[test]
+    fn segments_not_adding_up() {
+        let mut corrupt = Corrupt::new();
+        corrupt.add(0x14, 0x20);
+        corrupt.finds(Severity::Error, "add up to");
+    }
+
+    #[test]
+    fn section_past_the_end() {
+        let mut corrupt = Corrupt::new();
+        corrupt.add(0x20, 0x1000);
+        corrupt.finds(Severity::Error, "directory nodes run past the end");
+    }
+
+    #[test]
+    fn name_outside_the_table() {
+        let mut corrupt = Corrupt::new();
+        let a = corrupt.file(0, "a.bin");
+        corrupt.add(a + 4, 0xFFFF);
+        corrupt.finds(Severity::Error, "outside the string table");
+    }
+
+    #[test]
+    fn wrong_hash() {
+        let mut corrupt = Corrupt::new();
+        let a = corrupt.file(0, "a.bin");
+        corrupt.add(a, 1);
+        corrupt.finds(Severity::Error, "its name hashes to");
+    }
+
+    #[test]
+    fn children_past_the_nodes() {
+        let mut corrupt = Corrupt::new();
+        let root = corrupt.dir(0);
+        corrupt.add(root + 8, 0x100);
+        corrupt.finds(Severity::Error, "file nodes");
+    }
+
+    #[test]
+    fn missing_dot() {
+        let mut corrupt = Corrupt::new();
+        let (dot, dotdot) = (corrupt.file(0, "."), corrupt.file(0, ".."));
+        let name = get_u32(&corrupt.bytes, dotdot + 4);
+        put_u32(&mut corrupt.bytes, dot + 4, name);
+        corrupt.bytes.copy_within(dotdot + 2..dotdot + 4, dot + 2);
+        corrupt.finds(Severity::Error, "\".\" entries instead of 1");
+    }
+
+    #[test]
+    fn dot_elsewhere() {
+        let mut corrupt = Corrupt::new();
+        let dot = corrupt.file(0, ".");
+        put_u32(&mut corrupt.bytes, dot + 8, 1);
+        corrupt.finds(Severity::Error, "\".\" in \"stage\" points to directory 1");
+    }
+
+    #[test]
+    fn dotdot_elsewhere() {
+        let mut corrupt = Corrupt::new();
+        let dotdot = corrupt.file(2, "..");
+        put_u32(&mut corrupt.bytes, dotdot + 8, 0);
+        corrupt.finds(Severity::Error, "\"..\" in \"Model\" points to directory 0x0 instead of 0x1");
+    }
+
+    #[test]
+    fn unlinked_and_twice_linked_folders() {
+        let mut corrupt = Corrupt::new();
+        let jmp = corrupt.file(0, "jmp");
+        put_u32(&mut corrupt.bytes, jmp + 8, 2);
+        corrupt.finds(Severity::Warning, "\"jmp\" isn't inside any folder");
+        corrupt.finds(Severity::Warning, "\"Model\" is inside 2 folders");
+    }
+
+    #[test]
+    fn file_and_folder() {
+        let mut corrupt = Corrupt::new();
+        let a = corrupt.file(0, "a.bin");
+        corrupt.set_attr(a, A | FileAttr::FOLDER);
+        corrupt.finds(Severity::Error, "flagged both file and folder");
+    }
+
+    #[test]
+    fn folder_node_to_nowhere() {
+        let mut corrupt = Corrupt::new();
+        let jmp = corrupt.file(0, "jmp");
+        put_u32(&mut corrupt.bytes, jmp + 8, 99);
+        corrupt.finds(Severity::Error, "points to directory 99 which doesn't exist");
+    }
+
+    #[test]
+    fn neither_file_nor_folder() {
+        let mut corrupt = Corrupt::new();
+        let a = corrupt.file(0, "a.bin");
+        corrupt.set_attr(a, FileAttr::LOAD_TO_MRAM);
+        corrupt.finds(Severity::Warning, "flagged neither file nor folder");
+    }
+
+    #[test]
+    fn unsynced_id() {
+        let mut corrupt = Corrupt::new();
+        let a = corrupt.file(0, "a.bin");
+        corrupt.bytes[a + 1] ^= 0x40;
+        corrupt.finds(Severity::Error, "ids are synced");
+    }
+
+    #[test]
+    fn no_segment() {
+        let mut corrupt = Corrupt::new();
+        let a = corrupt.file(0, "a.bin");
+        corrupt.set_attr(a, FileAttr::FILE);
+        corrupt.finds(Severity::Warning, "isn't flagged MRAM, ARAM or DVD");
+    }
+
+    #[test]
+    fn data_past_the_end() {
+        let mut corrupt = Corrupt::new();
+        let a = corrupt.file(0, "a.bin");
+        corrupt.add(a + 0xC, 0x10000);
+        corrupt.finds(Severity::Error, "runs past the file data");
+    }
+
+    #[test]
+    fn data_outside_its_segment() {
+        let mut corrupt = Corrupt::new();
+        let b = corrupt.file(1, "b.bcsv");
+        put_u32(&mut corrupt.bytes, b + 8, 0);
+        corrupt.finds(Severity::Error, "outside the ARAM segment");
+    }
+
+    #[test]
+    fn compressed_flag_on_plain_data() {
+        let mut corrupt = Corrupt::new();
+        let a = corrupt.file(0, "a.bin");
+        corrupt.set_attr(a, A | FileAttr::COMPRESSED);
+        corrupt.finds(Severity::Warning, "isn't Yaz0 or Yay0");
+    }
+
+    #[test]
+    fn next_idx_off() {
+        let mut corrupt = Corrupt::new();
+        corrupt.bytes[0x39] += 1;
+        corrupt.finds(Severity::Warning, "next_idx is");
+    }
+
+    #[test]
+    fn data_within_other_data() {
+        let mut corrupt = Corrupt::new();
+        let (a, d) = (corrupt.file(0, "a.bin"), corrupt.file(0, "d.bin"));
+        let start = get_u32(&corrupt.bytes, a + 8);
+        put_u32(&mut corrupt.bytes, d + 8, start + 2);
+        corrupt.finds(Severity::Warning, "\"d.bin\" data lies within the data of \"a.bin\"");
+    }
+
+    #[test]
+    fn data_partially_overlapping() {
+        let mut corrupt = Corrupt::new();
+        let (a, d) = (corrupt.file(0, "a.bin"), corrupt.file(0, "d.bin"));
+        let start = get_u32(&corrupt.bytes, a + 8);
+        put_u32(&mut corrupt.bytes, d + 8, start + 8);
+        corrupt.finds(Severity::Error, "\"d.bin\" data partially overlaps the data of \"a.bin\"");
+    }
+
+    /// Renames `d.bin` in the string table, hash included.
+    fn rename_d(corrupt: &mut Corrupt, name: &[u8; 5]) {
+        let d = corrupt.file(0, "d.bin");
+        let off = corrupt.bytes.windows(6).position(|x| x == b"d.bin\0").unwrap();
+        corrupt.bytes[off..off + 5].copy_from_slice(name);
+        corrupt.bytes[d + 2..d + 4].copy_from_slice(&calc_hash_bytes(name).to_be_bytes());
+    }
+
+    #[test]
+    fn hash_shared_in_a_folder() {
+        // 'a' * 3 + '.' == 'b' * 3 + '+'
+        let mut corrupt = Corrupt::new();
+        rename_d(&mut corrupt, b"b+bin");
+        corrupt.finds(Severity::Error, "\"a.bin\" and \"b+bin\" in \"stage\" both hash to");
+    }
+
+    #[test]
+    fn name_twice_in_a_folder() {
+        let mut corrupt = Corrupt::new();
+        rename_d(&mut corrupt, b"a.bin");
+        corrupt.finds(Severity::Error, "\"a.bin\" is in \"stage\" twice");
+    }
+}
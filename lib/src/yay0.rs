@@ -0,0 +1,164 @@
+use crate::compression::{Level, default_threads};
+use crate::error::{RarcError, RarcResult};
+use crate::lz::{self, Token};
+
+/// Magic at the start of every Yay0 (SZP) stream.
+pub const MAGIC: &[u8; 4] = b"Yay0";
+
+/// Size of the Yay0 header: magic, decompressed size, link and chunk offsets.
+const HEADER_SIZE: usize = 0x10;
+
+fn read_u32(data: &[u8], off: usize) -> RarcResult<u32> {
+    data.get(off..off + 4)
+        .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+        .ok_or(RarcError::Truncated { section: "Yay0 stream", offset: off as u64 })
+}
+
+fn read_u16(data: &[u8], off: usize) -> RarcResult<u16> {
+    data.get(off..off + 2)
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+        .ok_or(RarcError::Truncated { section: "Yay0 link table", offset: off as u64 })
+}
+
+fn read_u8(data: &[u8], off: usize) -> RarcResult<u8> {
+    data.get(off).copied()
+        .ok_or(RarcError::Truncated { section: "Yay0 chunk data", offset: off as u64 })
+}
+
+/// Decompresses a whole Yay0 stream.
+pub fn decompress(data: &[u8]) -> RarcResult<Vec<u8>> {
+    if !data.starts_with(MAGIC) {
+        let mut magic = [0u8; 4];
+        let len = data.len().min(4);
+        magic[..len].copy_from_slice(&data[..len]);
+        return Err(RarcError::BadMagic { offset: 0, magic });
+    }
+    let size = read_u32(data, 4)? as usize;
+    let mut link = read_u32(data, 8)? as usize;
+    let mut chunk = read_u32(data, 12)? as usize;
+    let mut mask_off = HEADER_SIZE;
+    let mut result = Vec::with_capacity(lz::reserve(size, data.len()));
+    let (mut mask, mut bits) = (0u32, 0);
+    while result.len() < size {
+        if bits == 0 {
+            mask = read_u32(data, mask_off)?;
+            mask_off += 4;
+            bits = 32;
+        }
+        if mask & 0x8000_0000 != 0 {
+            result.push(read_u8(data, chunk)?);
+            chunk += 1;
+        } else {
+            let pair = read_u16(data, link)? as usize;
+            let dist = (pair & 0xFFF) + 1;
+            let len = match pair >> 12 {
+                0 => {
+                    chunk += 1;
+                    read_u8(data, chunk - 1)? as usize + 0x12
+                },
+                n => n + 2
+            };
+            if dist > result.len() {
+                return Err(RarcError::BadCompressedData { format: "Yay0", offset: link as u64 });
+            }
+            link += 2;
+            for _ in 0..len {
+                result.push(result[result.len() - dist]);
+            }
+        }
+        mask <<= 1;
+        bits -= 1;
+    }
+    result.truncate(size);
+    Ok(result)
+}
+
+/// Compresses `data` into a Yay0 stream using every available thread.
+pub fn compress(data: &[u8], level: Level) -> Vec<u8> {
+    compress_with(data, level, default_threads())
+}
+
+/// Compresses `data` into a Yay0 stream, searching with up to `threads` threads.
+pub fn compress_with(data: &[u8], level: Level, threads: usize) -> Vec<u8> {
+    let mut masks = vec![];
+    let mut links = vec![];
+    let mut chunks = vec![];
+    let (mut mask, mut bits) = (0u32, 0);
+    lz::tokenize(data, level, threads, |token| {
+        match token {
+            Token::Literal(byte) => {
+                mask |= 0x8000_0000 >> bits;
+                chunks.push(byte);
+            },
+            Token::Match { dist, len } if len < 0x12 => {
+                let pair = (len - 2) << 12 | (dist - 1);
+                links.extend_from_slice(&pair.to_be_bytes());
+            },
+            Token::Match { dist, len } => {
+                links.extend_from_slice(&(dist - 1).to_be_bytes());
+                chunks.push((len - 0x12) as u8);
+            }
+        }
+        bits += 1;
+        if bits == 32 {
+            masks.push(mask);
+            (mask, bits) = (0, 0);
+        }
+    });
+    if bits != 0 {
+        masks.push(mask);
+    }
+    let link_off = HEADER_SIZE + masks.len() * 4;
+    let chunk_off = link_off + links.len();
+    let mut result = Vec::with_capacity(chunk_off + chunks.len());
+    result.extend_from_slice(MAGIC);
+    result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    result.extend_from_slice(&(link_off as u32).to_be_bytes());
+    result.extend_from_slice(&(chunk_off as u32).to_be_bytes());
+    for mask in masks {
+        result.extend_from_slice(&mask.to_be_bytes());
+    }
+    result.extend_from_slice(&links);
+    result.extend_from_slice(&chunks);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lz::checks::{self, Codec};
+
+    const CODEC: Codec = Codec { compress: compress_with, decompress };
+
+    #[test]
+    fn round_trip_every_level() {
+        checks::round_trip_every_level(CODEC);
+    }
+
+    #[test]
+    fn same_output_for_any_thread_count() {
+        checks::same_output_for_any_thread_count(CODEC);
+    }
+
+    #[test]
+    fn known_stream() {
+        let expected = [
+            b"Yay0".as_slice(), &[0, 0, 0, 0x0a, 0, 0, 0, 0x14, 0, 0, 0, 0x16],
+            &[0x80, 0, 0, 0], &[0x70, 0x00], b"a"
+        ].concat();
+        assert_eq!(compress(b"aaaaaaaaaa", Level::default()), expected);
+        assert_eq!(decompress(&expected).unwrap(), b"aaaaaaaaaa");
+    }
+
+    #[test]
+    fn corrupt_streams_fail() {
+        checks::truncated_streams_fail(CODEC);
+        assert!(matches!(decompress(b"Yaz0\0\0\0\x01"), Err(RarcError::BadMagic { .. })));
+        // A match reaching back before the start of the output.
+        let back = [
+            b"Yay0".as_slice(), &[0, 0, 0, 0x03, 0, 0, 0, 0x14, 0, 0, 0, 0x16],
+            &[0, 0, 0, 0], &[0x10, 0x00]
+        ].concat();
+        assert!(matches!(decompress(&back), Err(RarcError::BadCompressedData { .. })));
+    }
+}
@@ -1,4 +1,4 @@
-use std::{io::Cursor, path::PathBuf};
+use std::{io::{BufRead, BufReader, BufWriter, Cursor, Read, Write}, path::{Path, PathBuf}};
 use rarc_lib::*;
 use clap::*;
 
@@ -45,6 +45,57 @@ impl From<Attr> for nodes::file::FileAttr {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum Wrapper {
+    #[default]
+    Yaz0,
+    Yay0,
+    None
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum ArchiveFormat {
+    #[default]
+    Rarc,
+    U8,
+    Sarc
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum Order {
+    #[default]
+    Alphabetical,
+    CaseInsensitive,
+    FilesFirst
+}
+
+impl From<Order> for archive::ImportOrder {
+    fn from(value: Order) -> Self {
+        match value {
+            Order::Alphabetical => Self::Alphabetical,
+            Order::CaseInsensitive => Self::CaseInsensitive,
+            Order::FilesFirst => Self::FilesFirst
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Encoding {
+    Utf8,
+    Sjis,
+    Raw
+}
+
+impl From<Encoding> for table::NameEncoding {
+    fn from(value: Encoding) -> Self {
+        match value {
+            Encoding::Utf8 => Self::Utf8,
+            Encoding::Sjis => Self::ShiftJis,
+            Encoding::Raw => Self::Raw
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Subcommand)]
 enum Compression {
     /// Compress the file with Naive lookback.
@@ -58,18 +109,23 @@ enum Compression {
         #[arg(short, long)]
         /// Lookback distance. Set between 1 and 10; 10 corresponds to greatest lookback distance.
         level: usize
-    }
+    },
+    /// Compress the file exactly like Nintendo's encoder, for byte-identical
+    /// retail files. Much slower than the others.
+    #[command(name = "compress-nintendo")]
+    Nintendo
 }
 
-impl From<Compression> for yaz0::CompressionLevel {
+impl From<Compression> for compression::Level {
     fn from(value: Compression) -> Self {
         match value {
             Compression::CompressLookAhead { level } => 
-                yaz0::CompressionLevel::Lookahead 
+                compression::Level::Lookahead 
                     { quality: usize::clamp(level, 1, 10) },
             Compression::CompressNaive { level } => 
-                yaz0::CompressionLevel::Naive 
-                    { quality: usize::clamp(level, 1, 10) }
+                compression::Level::Naive 
+                    { quality: usize::clamp(level, 1, 10) },
+            Compression::Nintendo => compression::Level::Nintendo
         }
     }
 }
@@ -80,13 +136,80 @@ impl Default for Compression {
     }
 }
 
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Print every node of an Archive as a tree.
+    List {
+        #[arg(required = true)]
+        /// The Archive to list.
+        file: PathBuf,
+        #[arg(short, long)]
+        /// Print one full path per line instead of a tree.
+        long: bool,
+        #[arg(short, long)]
+        /// Only descend this many folders below the root.
+        depth: Option<usize>
+    },
+    /// Print an Archive's headers, segment sizes and where its bytes go.
+    Info {
+        #[arg(required = true)]
+        /// The Archive to inspect.
+        file: PathBuf
+    },
+    /// Check an Archive's nodes, names and data layout for anything a game
+    /// would choke on. Exits with an error if anything but warnings is found.
+    Verify {
+        #[arg(required = true)]
+        /// The Archive to check.
+        file: PathBuf
+    },
+    /// List names sharing a hash anywhere in an Archive. Those in the same
+    /// folder can resolve to the wrong node in game.
+    Collisions {
+        #[arg(required = true)]
+        /// The Archive to check.
+        file: PathBuf
+    },
+    /// Swap an Archive between big endian RARC (Wii, GCN) and little endian
+    /// CRAR (Switch), keeping attributes, ids and order.
+    Convert {
+        #[arg(required = true)]
+        /// The Archive to convert.
+        file: PathBuf,
+        #[arg(required = true)]
+        /// Where to write the converted Archive.
+        output: PathBuf,
+        #[arg(short, long)]
+        /// What to wrap the result in, defaults to what the input was wrapped in.
+        wrapper: Option<Wrapper>
+    },
+    /// Unpack only the nodes matching in-archive paths or globs.
+    Extract {
+        #[arg(required = true)]
+        /// The Archive to extract from.
+        file: PathBuf,
+        #[arg(required = true)]
+        /// In-archive paths or globs, e.g. "stage/jmp/Placement/**/*.bcsv".
+        patterns: Vec<String>,
+        #[arg(short, long)]
+        /// Dir to extract into, defaults to next to the Archive.
+        output: Option<PathBuf>,
+        #[arg(long, conflicts_with = "output")]
+        /// Write the contents of the single matching file to stdout.
+        stdout: bool
+    },
+    #[command(flatten)]
+    Compression(Compression)
+}
+
 #[derive(Parser, Clone, Debug)]
-#[command(version, about, long_about = None, propagate_version = true)]
+#[command(version, about, long_about = None, propagate_version = true,
+    subcommand_negates_reqs = true)]
 struct Args {
     #[arg(required = true)]
     /// The input, if this is a file, attempt to unpack the Archive.
     /// If this is a directory, attempt to make an Archive.
-    pub input: PathBuf,
+    pub input: Option<PathBuf>,
     #[arg(short, long)]
     /// Optional output, must be a dir when unpacking and a file when packing.
     pub output: Option<PathBuf>,
@@ -102,49 +225,498 @@ struct Args {
     /// 
     /// dvd loads right off the DVD when needed (wii, gcn).
     pub attr: Attr,
+    #[arg(short, long = "rule", value_name = "PATTERN=ATTRS")]
+    /// Give files matching PATTERN other attributes than --attr, e.g.
+    /// "*.ast=dvd" or "Model/**=mram,yaz0". ATTRS is a comma separated mix of
+    /// mram, aram, dvd, yaz0 and yay0. Patterns like ".ast" match an
+    /// extension, patterns without a "/" match the file name alone.
+    ///
+    /// Can be given more than once, the first rule matching a file wins.
+    pub rules: Vec<String>,
+    #[arg(long, value_name = "FILE")]
+    /// A file of --rule rules, one per line, "#" starts a comment. Checked
+    /// after every --rule.
+    pub rules_file: Option<PathBuf>,
+    #[arg(long, default_value = "alphabetical")]
+    /// Order to add each folder's entries in when packing, which decides node
+    /// order and ids. A manifest still overrides it.
+    /// alphabetical sorts by name, byte for byte (default).
+    ///
+    /// case-insensitive sorts by name ignoring case.
+    ///
+    /// files-first puts files before folders, each by name.
+    pub order: Order,
+    #[arg(long, value_name = "FILE", conflicts_with = "order")]
+    /// A file listing paths below the packed dir, "/" separated, one per line
+    /// in the order to add them. Anything not listed follows by name.
+    pub order_file: Option<PathBuf>,
+    #[arg(short, long)]
+    /// When unpacking, also write a .rarc.json manifest next to the dir so
+    /// repacking keeps each node's attributes, ids and order.
+    pub manifest: bool,
+    #[arg(short, long)]
+    /// When unpacking, decompress files flagged as compressed inside the Archive.
+    pub decompress: bool,
+    #[arg(short, long, default_value = "yaz0")]
+    /// What to wrap a packed Archive in.
+    /// yaz0 is an .szs (default).
+    ///
+    /// yay0 is an SZP, used by some GameCube titles.
+    ///
+    /// none leaves the Archive uncompressed. Only none streams file data to
+    /// disk, yaz0 and yay0 need the whole Archive in memory to compress it.
+    pub wrapper: Wrapper,
+    #[arg(short, long, default_value = "rarc")]
+    /// What to pack a directory as, unpacking always goes by the file's magic.
+    /// rarc is a RARC or CRAR, depending on the endian (default).
+    ///
+    /// u8 is a Wii U8 archive, always big endian.
+    ///
+    /// sarc is a SARC (Wii U, Switch), use little endian for Switch.
+    pub format: ArchiveFormat,
+    #[arg(short = 'n', long, global = true)]
+    /// How names are stored inside the Archive, defaults to utf8 or, when
+    /// packing, whatever the manifest recorded.
+    ///
+    /// sjis is Shift-JIS, used by Japanese retail Archives.
+    ///
+    /// raw keeps each byte as the char with the same value.
+    pub encoding: Option<Encoding>,
+    #[arg(short = 'j', long)]
+    /// Threads to compress the wrapper with, defaults to every available one.
+    pub threads: Option<usize>,
     #[command(subcommand)]
-    pub compression: Option<Compression>
+    pub command: Option<Command>
+}
+
+/// Reads an Archive out of `data`, already decompressed, in whichever format
+/// it's in.
+fn parse_archive(data: &[u8], encoding: Option<Encoding>) -> RarcResult<Archive> {
+    let encoding = encoding.map_or_else(Default::default, Into::into);
+    let mut archive = Archive { encoding, ..Default::default() };
+    let mut reader = Cursor::new(data);
+    match Format::detect(data) {
+        Format::U8 => archive.read_u8(&mut reader)?,
+        Format::Sarc => archive.read_sarc(&mut reader)?,
+        _ => archive.read(&mut reader)?
+    }
+    Ok(archive)
+}
+
+fn read_archive(path: &Path, encoding: Option<Encoding>) -> RarcResult<Archive> {
+    let data = std::fs::read(path)?;
+    parse_archive(&compression::decompress_payload(&data)?, encoding)
+}
+
+/// Writes `archive` as `format`, front to back.
+fn write_archive<W: Write>(archive: &Archive, format: ArchiveFormat, endian: Endian, writer: &mut W)
+    -> RarcResult<()> {
+    match format {
+        ArchiveFormat::Rarc => archive.write(writer, endian.into()),
+        ArchiveFormat::U8 => archive.write_u8(writer),
+        ArchiveFormat::Sarc => archive.write_sarc(writer, endian.into())
+    }
 }
 
-fn main() -> binrw::BinResult<()> {
+/// Short, readable form of the load and compression flags.
+fn attr_flags(attr: FileAttr) -> String {
+    let flags = [
+        (FileAttr::LOAD_TO_MRAM, "MRAM"),
+        (FileAttr::LOAD_TO_ARAM, "ARAM"),
+        (FileAttr::LOAD_FROM_DVD, "DVD"),
+        (FileAttr::COMPRESSED, "COMP"),
+        (FileAttr::USE_SZS, "SZS")
+    ];
+    let names: Vec<_> = flags.iter()
+        .filter(|(flag, _)| attr.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect();
+    if names.is_empty() {
+        String::from("-")
+    } else {
+        names.join("|")
+    }
+}
+
+fn list_dir<W: Write>(out: &mut W, index: &lazy::Index, dir: usize, level: usize, long: bool,
+    depth: Option<usize>) -> std::io::Result<()> {
+    for child in index.children(dir) {
+        if child.is_shortcut() {
+            continue;
+        }
+        let name = match long {
+            true => index.path(child),
+            false => format!("{}{}", "  ".repeat(level + 1), child.name)
+        };
+        if child.is_dir() {
+            writeln!(out, "{:>10} {:<14} {:>5} {:#06x} {:>10}  {}/", "-", "DIR", "-",
+                child.node.hash, "-", name)?;
+            if depth.is_none_or(|d| level < d)
+                && let Some(folder) = child.folder() {
+                list_dir(out, index, folder, level + 1, long, depth)?;
+            }
+        } else {
+            writeln!(out, "{:>10} {:<14} {:>5} {:#06x} {:#010x}  {}", child.node.data_size,
+                attr_flags(child.attr), child.node.id, child.node.hash, child.node.data, name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads only the nodes and names of the Archive at `path`, along with the
+/// format found under any wrapper. Uncompressed Archives are never loaded
+/// whole. Other formats are indexed as the Archive they'd convert to.
+fn read_index(path: &Path, encoding: Option<Encoding>) -> RarcResult<(Format, lazy::Index)> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let format = Format::detect(reader.fill_buf()?);
+    if matches!(format, Format::Rarc | Format::Crar) {
+        let index = lazy::Index::read(&mut reader, encoding.map_or_else(Default::default, Into::into))?;
+        return Ok((format, index));
+    }
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let data = compression::decompress_payload(&data)?;
+    let format = Format::detect(&data);
+    if matches!(format, Format::Rarc | Format::Crar) {
+        let index = lazy::Index::read(&mut Cursor::new(data), encoding.map_or_else(Default::default, Into::into))?;
+        return Ok((format, index));
+    }
+    let archive = parse_archive(&data, encoding)?;
+    let index = lazy::Index::read(&mut Cursor::new(archive.to_bytes(binrw::Endian::Big)?), archive.encoding)?;
+    Ok((format, index))
+}
+
+fn list(file: &Path, long: bool, depth: Option<usize>, encoding: Option<Encoding>)
+    -> RarcResult<()> {
+    let (_, index) = read_index(file, encoding)?;
+    let mut out = std::io::stdout().lock();
+    let root = index.folders.first()
+        .ok_or(RarcError::BadDirectoryIndex { node: 0, index: 0 })?;
+    writeln!(out, "{:>10} {:<14} {:>5} {:>6} {:>10}  PATH", "SIZE", "ATTR", "ID", "HASH", "OFFSET")?;
+    writeln!(out, "{:>10} {:<14} {:>5} {:#06x} {:>10}  {}/", "-", "DIR", "-",
+        root.node.hash, "-", root.name)?;
+    if depth != Some(0) {
+        list_dir(&mut out, &index, 0, 0, long, depth.map(|d| d - 1))?;
+    }
+    Ok(())
+}
+
+fn info(file: &Path, encoding: Option<Encoding>) -> RarcResult<()> {
+    let mut out = std::io::stdout().lock();
+    let mut reader = BufReader::new(std::fs::File::open(file)?);
+    let len = reader.get_ref().metadata()?.len();
+    let head = reader.fill_buf()?;
+    writeln!(out, "File:     {} ({} bytes)", file.display(), len)?;
+    match Format::detect(head) {
+        Format::Yaz0(header) => writeln!(out, "Wrapper:  Yaz0, {} bytes decompressed, alignment {:#x}",
+            header.size, header.alignment)?,
+        Format::Yay0 => match head.get(4..8) {
+            Some(size) => writeln!(out, "Wrapper:  Yay0, {} bytes decompressed",
+                u32::from_be_bytes([size[0], size[1], size[2], size[3]]))?,
+            None => writeln!(out, "Wrapper:  Yay0, truncated")?
+        },
+        _ => writeln!(out, "Wrapper:  none")?
+    }
+    let (format, index) = read_index(file, encoding)?;
+    let endian = match index.endian {
+        binrw::Endian::Big => "big",
+        binrw::Endian::Little => "little"
+    };
+    match format {
+        Format::Rarc => writeln!(out, "Format:   RARC, {} endian", endian)?,
+        Format::Crar => writeln!(out, "Format:   CRAR, {} endian", endian)?,
+        Format::U8 => writeln!(out, "Format:   U8, shown as the RARC it converts to")?,
+        Format::Sarc => writeln!(out, "Format:   SARC, shown as the RARC it converts to")?,
+        _ => writeln!(out, "Format:   {:?}", format)?
+    }
+    let (header, data_header) = (&index.header, &index.data_header);
+    writeln!(out, "\nHeader")?;
+    for (name, value) in [
+        ("size", header.size),
+        ("data_header_off", header.data_header_off),
+        ("file_data_off", header.file_data_off),
+        ("file_data_len", header.file_data_len),
+        ("mram_size", header.mram_size),
+        ("aram_size", header.aram_size),
+        ("dvd_size", header.dvd_size)
+    ] {
+        writeln!(out, "  {:<16} {:#010x} {:>10}", name, value, value)?;
+    }
+    writeln!(out, "\nDataHeader")?;
+    for (name, value) in [
+        ("dir_node_count", data_header.dir_node_count),
+        ("dir_node_off", data_header.dir_node_off),
+        ("file_node_count", data_header.file_node_count),
+        ("file_node_off", data_header.file_node_off),
+        ("string_tbl_size", data_header.string_tbl_size),
+        ("string_tbl_off", data_header.string_tbl_off),
+        ("next_idx", data_header.next_idx as u32)
+    ] {
+        writeln!(out, "  {:<16} {:#010x} {:>10}", name, value, value)?;
+    }
+    writeln!(out, "  {:<16} {}", "sync", data_header.sync)?;
+    let stats = index.stats();
+    writeln!(out, "\nSegments")?;
+    for (name, segment) in [("MRAM", stats.mram), ("ARAM", stats.aram), ("DVD", stats.dvd)] {
+        writeln!(out, "  {:<16} {:>5} files {:>10} bytes", name, segment.files, segment.size)?;
+    }
+    writeln!(out, "\nNodes")?;
+    writeln!(out, "  {:<16} {:>10}", "folders", stats.folders)?;
+    writeln!(out, "  {:<16} {:>10}", "files", stats.files)?;
+    writeln!(out, "  {:<16} {:>10}", "folder nodes", stats.folder_nodes)?;
+    writeln!(out, "\nBytes")?;
+    for (name, value) in [
+        ("file data", stats.data_bytes),
+        ("stored data", stats.unique_bytes),
+        ("dedup saved", stats.dedup_saved),
+        ("data padding", stats.data_padding),
+        ("node padding", stats.node_padding),
+        ("table padding", stats.table_padding),
+        ("total padding", stats.total_padding)
+    ] {
+        writeln!(out, "  {:<16} {:>10}", name, value)?;
+    }
+    Ok(())
+}
+
+fn verify(file: &Path, encoding: Option<Encoding>) -> RarcResult<()> {
+    let data = std::fs::read(file)?;
+    let data = compression::decompress_payload(&data)?;
+    let encoding = encoding.map_or_else(Default::default, Into::into);
+    let report = verify::verify(&mut Cursor::new(data), encoding)?;
+    let mut out = std::io::stdout().lock();
+    for issue in &report.issues {
+        writeln!(out, "{}", issue)?;
+    }
+    let errors = report.errors().count();
+    writeln!(out, "{}: {} errors, {} warnings", file.display(), errors, report.warnings().count())?;
+    if errors != 0 {
+        out.flush()?;
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn collisions(file: &Path, encoding: Option<Encoding>) -> RarcResult<()> {
+    let archive = read_archive(file, encoding)?;
+    let folder = archive.folder_collisions();
+    let all = archive.hash_collisions();
+    let mut out = std::io::stdout().lock();
+    for collision in &all {
+        let same = folder.iter().any(|x| x.hash == collision.hash);
+        writeln!(out, "{:#06x}{}", collision.hash, if same { "  same folder" } else { "" })?;
+        for path in &collision.paths {
+            writeln!(out, "  {}", path)?;
+        }
+    }
+    writeln!(out, "{}: {} shared hashes, {} within a folder", file.display(), all.len(), folder.len())?;
+    Ok(())
+}
+
+/// Wraps a written Archive, padding compressed ones to 32 bytes.
+fn wrap(data: Vec<u8>, wrapper: Wrapper, level: compression::Level, threads: usize) -> Vec<u8> {
+    let mut data = match wrapper {
+        Wrapper::Yaz0 => szs::compress_with(&data, level, threads),
+        Wrapper::Yay0 => yay0::compress_with(&data, level, threads),
+        Wrapper::None => return data
+    };
+    let mut size = data.len();
+    size = size.next_multiple_of(32) - size;
+    let mut extra = vec![0u8; size];
+    data.append(&mut extra);
+    data
+}
+
+fn convert(file: &Path, output: &Path, wrapper: Option<Wrapper>, threads: Option<usize>) -> RarcResult<()> {
+    let input = std::fs::read(file)?;
+    let wrapper = wrapper.unwrap_or(match Format::detect(&input) {
+        Format::Yaz0(_) => Wrapper::Yaz0,
+        Format::Yay0 => Wrapper::Yay0,
+        _ => Wrapper::None
+    });
+    let mut data = vec![];
+    let endian = Archive::convert_endian(&mut Cursor::new(compression::decompress_payload(&input)?), &mut data)?;
+    let threads = threads.unwrap_or_else(compression::default_threads);
+    std::fs::write(output, wrap(data, wrapper, Compression::default().into(), threads))?;
+    let format = match endian {
+        binrw::Endian::Big => "RARC",
+        binrw::Endian::Little => "CRAR"
+    };
+    println!("Converted to {} at {:?}", format, std::path::absolute(output)?);
+    Ok(())
+}
+
+fn extract(file: &Path, patterns: &[String], output: Option<PathBuf>, stdout: bool,
+    encoding: Option<Encoding>) -> RarcResult<()> {
+    let archive = read_archive(file, encoding)?;
+    if stdout {
+        let mut matches = vec![];
+        for pattern in patterns {
+            matches.extend(archive.find_by_glob(pattern)?.into_iter()
+                .filter(|x| x.borrow().is_file()));
+        }
+        match matches.as_slice() {
+            [file] => { std::io::copy(&mut file.borrow().open_data()?, &mut std::io::stdout())?; },
+            [] => {
+                eprintln!("No file matched {:?}", patterns);
+                std::process::exit(1);
+            },
+            _ => {
+                eprintln!("{} files matched {:?}, --stdout needs exactly one", matches.len(), patterns);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    let dir = match output {
+        Some(dir) => dir,
+        None => unpack_dir(file)?
+    };
+    let written = archive.extract(patterns, &dir)?;
+    if written.is_empty() {
+        eprintln!("Nothing matched {:?}", patterns);
+        std::process::exit(1);
+    }
+    for path in written {
+        println!("Extracted {:?}", path);
+    }
+    Ok(())
+}
+
+/// A closed pipe, e.g. `list x.arc | head`, just means nobody wants the rest
+/// of the output.
+fn ignore_broken_pipe(result: RarcResult<()>) -> RarcResult<()> {
+    match result {
+        Err(RarcError::Io(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result
+    }
+}
+
+/// Unpacking goes next to the input, or to the current dir if it has no parent.
+fn unpack_dir(input: &Path) -> RarcResult<PathBuf> {
+    match input.parent() {
+        Some(parent) if !parent.to_string_lossy().is_empty() => Ok(parent.into()),
+        _ => Ok(std::env::current_dir()?)
+    }
+}
+
+fn main() -> RarcResult<()> {
     let args = Args::parse();
     let Args { input, output,
-        endian, attr, compression} = args;
+        endian, attr, rules, rules_file, order, order_file, manifest, decompress, wrapper, format, encoding, threads, command} = args;
+    let compression = match command {
+        Some(Command::List { file, long, depth }) => return ignore_broken_pipe(list(&file, long, depth, encoding)),
+        Some(Command::Info { file }) => return ignore_broken_pipe(info(&file, encoding)),
+        Some(Command::Verify { file }) => return ignore_broken_pipe(verify(&file, encoding)),
+        Some(Command::Collisions { file }) => return ignore_broken_pipe(collisions(&file, encoding)),
+        Some(Command::Convert { file, output, wrapper }) => return convert(&file, &output, wrapper, threads),
+        Some(Command::Extract { file, patterns, output, stdout }) =>
+            return extract(&file, &patterns, output, stdout, encoding),
+        Some(Command::Compression(compression)) => Some(compression),
+        None => None
+    };
+    let Some(input) = input else {
+        Args::command().error(clap::error::ErrorKind::MissingRequiredArgument,
+            "the following required arguments were not provided:\n  <INPUT>").exit();
+    };
     if input.is_file() {
-        let mut data = std::fs::read(&input)?;
-        data = decompres_yaz0(data);
-        let mut reader = Cursor::new(data);
-        let mut archive = Archive::default();
-        archive.read(&mut reader)?;
-        let name = &archive.root.borrow().name;
-        if let Some(parent) = input.parent()
-            && !parent.to_string_lossy().is_empty() {
-            archive.unpack(&parent)?;
-            println!("Unpacked to {:?}", parent.join(name));
-        } else {
-            let dir = std::env::current_dir()?;
-            archive.unpack(&dir)?;
-            println!("Unpacked to {:?}", dir.join(name));
-        }
+        let archive = read_archive(&input, encoding)?;
+        let dir = unpack_dir(&input)?;
+        let options = archive::UnpackOptions { manifest, decompress };
+        let path = archive.unpack_with(&dir, options)?;
+        println!("Unpacked to {:?}", path);
     } else if input.is_dir() {
         let name = input.file_name().unwrap().to_string_lossy();
+        let level: compression::Level = compression.unwrap_or_default().into();
         let mut archive = Archive::create(name, true);
-        archive.import(&input, attr.into())?;
-        let mut data = archive.to_bytes(endian.into())?;
-        let level = compression.unwrap_or_default();
-        data = compress_yaz0(data, level.into());
-        let mut size = data.len();
-        size = size.next_multiple_of(32) - size;
-        let mut extra = vec![0u8; size];
-        data.append(&mut extra);
-        if let Some(out) = &output {
-            std::fs::write(out, data)?;
-            println!("Packed to {:?}", std::path::absolute(out)?);
-        } else {
-            let path = input.with_extension("arc");
-            std::fs::write(&path, data)?;
+        archive.compression = level;
+        let order = match order_file {
+            Some(file) => archive::ImportOrder::Explicit(std::fs::read_to_string(file)?.lines()
+                .map(|x| x.trim().trim_matches('/').to_owned())
+                .filter(|x| !x.is_empty())
+                .collect()),
+            None => order.into()
+        };
+        let mut options = archive::ImportOptions {
+            attr: attr.into(),
+            order,
+            encoding: encoding.map(Into::into),
+            check_collisions: matches!(format, ArchiveFormat::Rarc),
+            ..Default::default()
+        };
+        for rule in &rules {
+            options.rules.push(rules::AttrRule::parse(rule)?);
+        }
+        if let Some(file) = rules_file {
+            options.rules.extend(rules::AttrRule::parse_list(&std::fs::read_to_string(file)?)?);
+        }
+        archive.import_with(&input, &options)?;
+        let path = match &output {
+            Some(out) => out.clone(),
+            None => input.with_extension("arc")
+        };
+        let threads = threads.unwrap_or_else(compression::default_threads);
+        if let Wrapper::None = wrapper {
+            // Nothing to compress, so stream file data straight to disk.
+            let mut writer = BufWriter::new(std::fs::File::create(&path)?);
+            write_archive(&archive, format, endian, &mut writer)?;
+            writer.flush()?;
             println!("Packed to {:?}", std::path::absolute(path)?);
+            return Ok(());
         }
+        // The compressors work on the whole Archive at once, so it's built
+        // in memory first.
+        let mut data = vec![];
+        write_archive(&archive, format, endian, &mut data)?;
+        std::fs::write(&path, wrap(data, wrapper, level, threads))?;
+        println!("Packed to {:?}", std::path::absolute(path)?);
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attr_flags_names_each_flag() {
+        assert_eq!(attr_flags(FileAttr::FILE), "-");
+        assert_eq!(attr_flags(FileAttr::FILE | FileAttr::LOAD_TO_MRAM), "MRAM");
+        assert_eq!(attr_flags(FileAttr::FILE | FileAttr::LOAD_FROM_DVD | FileAttr::COMPRESSED | FileAttr::USE_SZS),
+            "DVD|COMP|SZS");
+    }
+
+    #[test]
+    fn rules_file_parses() {
+        let args = Args::try_parse_from(["rarc_tool", "dir", "--rules-file", "rules.txt", "-r", "*.ast=dvd"]).unwrap();
+        assert_eq!(args.rules_file, Some(PathBuf::from("rules.txt")));
+        assert_eq!(args.rules, ["*.ast=dvd"]);
+        assert!(Args::try_parse_from(["rarc_tool", "dir", "--rules", "rules.txt"]).is_err());
+    }
+
+    #[test]
+    fn list_parses() {
+        let args = Args::try_parse_from(["rarc_tool", "list", "x.arc", "--long", "-d", "2"]).unwrap();
+        assert!(matches!(args.command, Some(Command::List { long: true, depth: Some(2), .. })));
+        assert!(Args::try_parse_from(["rarc_tool", "list"]).is_err());
+    }
+
+    #[test]
+    fn list_dir_writes_the_tree() {
+        let mut archive = Archive::create("stage", true);
+        archive.add_dir("stage", "jmp").unwrap();
+        archive.add_file("stage/jmp", "b.bcsv", vec![1; 0x30], FileAttr::LOAD_TO_ARAM).unwrap();
+        archive.add_file("stage", "a.bin", vec![2; 10], FileAttr::LOAD_TO_MRAM).unwrap();
+        let bytes = archive.to_bytes(binrw::Endian::Big).unwrap();
+        let index = lazy::Index::read(&mut Cursor::new(bytes), table::NameEncoding::Utf8).unwrap();
+        let mut out = vec![];
+        list_dir(&mut out, &index, 0, 0, true, None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let paths: Vec<_> = out.lines().map(|x| x.rsplit("  ").next().unwrap()).collect();
+        assert_eq!(paths, ["stage/jmp/", "stage/jmp/b.bcsv", "stage/a.bin"]);
+        assert!(out.lines().last().unwrap().trim_start().starts_with("10 MRAM"));
+        let mut out = vec![];
+        list_dir(&mut out, &index, 0, 0, false, Some(0)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+    }
+}